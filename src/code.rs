@@ -0,0 +1,158 @@
+use crate::{diagnostics::Severity, error::ErrorKind};
+
+/// 每种错误与警告的稳定编号，可以在 dlua.json 中调整严重程度，或用 `-- @allow(...)` 忽略。
+/// 编号一经发布不再改变含义：00xx 为 require，01xx 为宏，02xx 为生成代码，03xx 为读写与配置
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Code {
+    /// DL0001：require 的模块在查找路径中不存在
+    UnresolvedRequire,
+    /// DL0002：require 的参数不是字符串常量
+    DynamicRequire,
+    /// DL0003：require 关系中存在环
+    RequireCycle,
+    /// DL0004：`-- @requires` 注释的格式有误
+    MalformedRequires,
+    /// DL0100：展开时无法理解的源码，如缺少变量名、提前结束
+    MalformedSource,
+    /// DL0101：宏定义的格式有误
+    MalformedMacro,
+    /// DL0102：宏调用的参数数量与定义不符
+    MacroArity,
+    /// DL0103：宏调用缺少右括号
+    UnclosedMacroCall,
+    /// DL0104：在局部作用域中定义全局宏
+    GlobalMacroInLocalScope,
+    /// DL0105：为已被局部变量遮蔽的宏创建别名
+    AliasShadowed,
+    /// DL0106：别名指向的宏不存在
+    AliasNotFound,
+    /// DL0107：别名注释的格式有误
+    MalformedAlias,
+    /// DL0108：导入的两个模块定义了同名但不同的宏
+    ImportConflict,
+    /// DL0109：`-- @use` 注释的格式有误
+    MalformedUse,
+    /// DL0110：`-- @use` 列出的宏不在模块导出的宏中
+    UseNotFound,
+    /// DL0201：生成的代码不是合法的 Lua
+    InvalidOutput,
+    /// DL0301：读写文件失败
+    Io,
+    /// DL0302：配置有误
+    Config,
+    /// DL0900：dlua 自身的错误
+    Internal,
+}
+
+impl Code {
+    pub const ALL: [Code; 19] = [
+        Code::UnresolvedRequire,
+        Code::DynamicRequire,
+        Code::RequireCycle,
+        Code::MalformedRequires,
+        Code::MalformedSource,
+        Code::MalformedMacro,
+        Code::MacroArity,
+        Code::UnclosedMacroCall,
+        Code::GlobalMacroInLocalScope,
+        Code::AliasShadowed,
+        Code::AliasNotFound,
+        Code::MalformedAlias,
+        Code::ImportConflict,
+        Code::MalformedUse,
+        Code::UseNotFound,
+        Code::InvalidOutput,
+        Code::Io,
+        Code::Config,
+        Code::Internal,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Code::UnresolvedRequire => "DL0001",
+            Code::DynamicRequire => "DL0002",
+            Code::RequireCycle => "DL0003",
+            Code::MalformedRequires => "DL0004",
+            Code::MalformedSource => "DL0100",
+            Code::MalformedMacro => "DL0101",
+            Code::MacroArity => "DL0102",
+            Code::UnclosedMacroCall => "DL0103",
+            Code::GlobalMacroInLocalScope => "DL0104",
+            Code::AliasShadowed => "DL0105",
+            Code::AliasNotFound => "DL0106",
+            Code::MalformedAlias => "DL0107",
+            Code::ImportConflict => "DL0108",
+            Code::MalformedUse => "DL0109",
+            Code::UseNotFound => "DL0110",
+            Code::InvalidOutput => "DL0201",
+            Code::Io => "DL0301",
+            Code::Config => "DL0302",
+            Code::Internal => "DL0900",
+        }
+    }
+
+    /// 简短的英文说明，用于 SARIF 的规则列表
+    pub fn summary(&self) -> &'static str {
+        match self {
+            Code::UnresolvedRequire => "unresolved require",
+            Code::DynamicRequire => "dynamic require",
+            Code::RequireCycle => "require cycle",
+            Code::MalformedRequires => "malformed requires annotation",
+            Code::MalformedSource => "malformed source",
+            Code::MalformedMacro => "malformed macro definition",
+            Code::MacroArity => "macro arity mismatch",
+            Code::UnclosedMacroCall => "unclosed macro call",
+            Code::GlobalMacroInLocalScope => "global macro in local scope",
+            Code::AliasShadowed => "alias of shadowed macro",
+            Code::AliasNotFound => "alias target not found",
+            Code::MalformedAlias => "malformed alias",
+            Code::ImportConflict => "conflicting imported macro",
+            Code::MalformedUse => "malformed use directive",
+            Code::UseNotFound => "used macro not exported",
+            Code::InvalidOutput => "invalid generated output",
+            Code::Io => "io error",
+            Code::Config => "invalid configuration",
+            Code::Internal => "internal error",
+        }
+    }
+
+    /// 由 DLxxxx 形式的编号查找
+    pub fn parse(code: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.as_str() == code)
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Code::UnresolvedRequire
+            | Code::DynamicRequire
+            | Code::RequireCycle
+            | Code::MalformedRequires => ErrorKind::Require,
+            Code::InvalidOutput => ErrorKind::Syntax,
+            Code::Io => ErrorKind::Io,
+            Code::Config => ErrorKind::Config,
+            _ => ErrorKind::Macro,
+        }
+    }
+
+    pub fn default_severity(&self) -> Severity {
+        match self {
+            Code::UnresolvedRequire
+            | Code::DynamicRequire
+            | Code::RequireCycle
+            | Code::ImportConflict => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+
+    /// 能否降级为警告或忽略。出错后无法得到完整输出的错误只能保持为错误
+    pub fn can_demote(&self) -> bool {
+        matches!(
+            self,
+            Code::UnresolvedRequire
+                | Code::DynamicRequire
+                | Code::RequireCycle
+                | Code::ImportConflict
+                | Code::InvalidOutput
+        )
+    }
+}
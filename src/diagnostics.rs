@@ -0,0 +1,398 @@
+use colored::Colorize;
+use serde_json::{Value, json};
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::{
+    code::Code,
+    error::{Error, Location},
+    severity::{Level, SeverityConfig},
+};
+
+/// 诊断信息的严重程度
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// 一条错误或警告：错误本身，加上按编号与配置决定的严重程度
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub error: Error,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+impl Diagnostic {
+    /// 按编号的默认严重程度报告一个错误
+    pub fn new(error: Error) -> Self {
+        Diagnostic {
+            severity: error.code.default_severity(),
+            error,
+        }
+    }
+
+    /// `--message-format json` 输出的一条记录，位置的行列号从 1 开始，结束位置不含在区间内
+    pub fn to_json(&self) -> Value {
+        let err = &self.error;
+        let mut value = json!({
+            "severity": self.severity.name(),
+            "code": err.code.as_str(),
+            "kind": err.kind.name(),
+            "message": err.message,
+            "macro": err.macro_name,
+            "related": err.labels.iter().map(|label| {
+                let mut related = location_json(&label.location);
+                related["message"] = json!(label.message);
+                related
+            }).collect::<Vec<_>>(),
+            "notes": err.notes,
+            "help": err.help,
+        });
+        let location = err.location.as_ref().map_or(
+            json!({
+                "file": null,
+                "line": null,
+                "column": null,
+                "end_line": null,
+                "end_column": null,
+            }),
+            location_json,
+        );
+        if let (Value::Object(value), Value::Object(location)) = (&mut value, location) {
+            value.extend(location);
+        }
+        value
+    }
+
+    /// 按 rustc 的风格渲染：标题、出错的源码行与下划线、次要位置的标注，以及 note 与 help
+    ///
+    /// ```text
+    /// error[DL0105]: macro shadowed is shadowed in current scope, cannot create alias a
+    ///   --> src/main.lua:4:20
+    ///    |
+    ///  2 | local shadowed = 1
+    ///    |       -------- shadowed here
+    ///  3 |
+    ///  4 | ---@alias local a = shadowed
+    ///    |                     ^^^^^^^^
+    /// ```
+    pub fn render(&self) -> String {
+        let err = &self.error;
+        let title = format!("{}[{}]", self.severity.name(), err.code.as_str());
+        let title = match self.severity {
+            Severity::Error => title.red().bold(),
+            Severity::Warning => title.yellow().bold(),
+        };
+        let mut out = format!("{}{} {}", title, ":".bold(), err.message.bold());
+
+        // 与主位置同一文件的标注画在同一段源码中，其它文件的标注各自成段
+        let mut snippets: Vec<Vec<Annotation>> = Vec::new();
+        if let Some(location) = &err.location {
+            snippets.push(vec![Annotation {
+                location,
+                message: "",
+                primary: true,
+            }]);
+        }
+        for label in &err.labels {
+            let annotation = Annotation {
+                location: &label.location,
+                message: &label.message,
+                primary: false,
+            };
+            match snippets
+                .iter_mut()
+                .find(|s| s[0].location.path == label.location.path)
+            {
+                Some(snippet) => snippet.push(annotation),
+                None => snippets.push(vec![annotation]),
+            }
+        }
+
+        let mut notes: Vec<String> = Vec::new();
+        if let Some(name) = &err.macro_name {
+            notes.push(format!("in expansion of macro {}", name));
+        }
+        notes.extend(err.notes.iter().cloned());
+
+        let width = snippets
+            .iter()
+            .flatten()
+            .map(|a| a.location.line.to_string().len())
+            .max()
+            .unwrap_or(0);
+        let gutter = " ".repeat(width);
+        let bar = "|".blue().bold();
+        for (i, snippet) in snippets.iter_mut().enumerate() {
+            let first = &snippet[0].location;
+            let arrow = if i == 0 { "-->" } else { ":::" };
+            out.push_str(&format!(
+                "\n{}{} {}:{}:{}",
+                gutter,
+                arrow.blue().bold(),
+                first.path.display(),
+                first.line,
+                char_column(first)
+            ));
+            out.push_str(&format!("\n{} {}", gutter, bar));
+            snippet.sort_by_key(|a| (a.location.line, !a.primary, a.location.column));
+            let mut prev_line: Option<usize> = None;
+            for annotation in snippet.iter() {
+                let location = annotation.location;
+                if prev_line != Some(location.line) {
+                    if prev_line.is_some_and(|prev| location.line > prev + 1) {
+                        out.push_str(&format!("\n{}", "...".blue().bold()));
+                    }
+                    out.push_str(&format!(
+                        "\n{} {} {}",
+                        format!("{:>width$}", location.line).blue().bold(),
+                        bar,
+                        expand_tabs(&location.source_line)
+                    ));
+                }
+                prev_line = Some(location.line);
+                out.push_str(&format!(
+                    "\n{} {} {}",
+                    gutter,
+                    bar,
+                    annotation.underline(self.severity)
+                ));
+            }
+        }
+        if !snippets.is_empty() && (!notes.is_empty() || err.help.is_some()) {
+            out.push_str(&format!("\n{} {}", gutter, bar));
+        }
+        for note in &notes {
+            out.push_str(&format!(
+                "\n{} {} note: {}",
+                gutter,
+                "=".blue().bold(),
+                note
+            ));
+        }
+        if let Some(help) = &err.help {
+            out.push_str(&format!(
+                "\n{} {} help: {}",
+                gutter,
+                "=".blue().bold(),
+                help
+            ));
+        }
+        out
+    }
+}
+
+fn location_json(location: &Location) -> Value {
+    json!({
+        "file": location.path.display().to_string(),
+        "line": location.line,
+        "column": location.column,
+        "end_line": location.end_line,
+        "end_column": location.end_column,
+    })
+}
+
+/// 源码片段中的一处标注
+struct Annotation<'a> {
+    location: &'a Location,
+    message: &'a str,
+    // 主位置用 ^ 标出，次要位置用 -
+    primary: bool,
+}
+
+impl Annotation<'_> {
+    /// 标注行：与源码对齐的下划线及说明
+    fn underline(&self, severity: Severity) -> String {
+        let line = &self.location.source_line;
+        let start = (self.location.column - 1).min(line.len());
+        // 跨行的区间只标到行尾，空区间（如文件末尾）至少标一个字符
+        let len = self.location.span.len().min(line.len() - start);
+        let indent = display_width(&expand_tabs(&line[..start]));
+        let width = display_width(&expand_tabs(&line[start..start + len])).max(1);
+        let mark = if self.primary { "^" } else { "-" }.repeat(width);
+        let text = if self.message.is_empty() {
+            mark
+        } else {
+            format!("{} {}", mark, self.message)
+        };
+        let text = match (self.primary, severity) {
+            (true, Severity::Error) => text.red().bold(),
+            (true, Severity::Warning) => text.yellow().bold(),
+            (false, _) => text.blue().bold(),
+        };
+        format!("{}{}", " ".repeat(indent), text)
+    }
+}
+
+/// 源码中的制表符按 4 个空格显示，保证下划线对齐
+fn expand_tabs(text: &str) -> String {
+    text.replace('\t', "    ")
+}
+
+/// 渲染时显示的列号按字符计算，Location 中的列号是字节偏移
+fn char_column(location: &Location) -> usize {
+    let line = &location.source_line;
+    let start = (location.column - 1).min(line.len());
+    line.get(..start)
+        .map_or(start, |prefix| prefix.chars().count())
+        + 1
+}
+
+/// 文本在终端中占的列数：中日韩文字与全角符号占两列，其余字符占一列
+fn display_width(text: &str) -> usize {
+    text.chars()
+        .map(|ch| match ch as u32 {
+            0x1100..=0x115F
+            | 0x2E80..=0x303E
+            | 0x3041..=0x33FF
+            | 0x3400..=0x4DBF
+            | 0x4E00..=0x9FFF
+            | 0xA000..=0xA4CF
+            | 0xAC00..=0xD7A3
+            | 0xF900..=0xFAFF
+            | 0xFE30..=0xFE4F
+            | 0xFF00..=0xFF60
+            | 0xFFE0..=0xFFE6
+            | 0x1F300..=0x1F64F
+            | 0x1F900..=0x1F9FF
+            | 0x20000..=0x3FFFD => 2,
+            _ => 1,
+        })
+        .sum()
+}
+
+/// 由 Error 直接转换时总是错误，之后可以在 [`Diagnostics::push`] 中按配置降级
+impl From<Error> for Diagnostic {
+    fn from(error: Error) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            error,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+/// 收集一次构建中所有文件的错误与警告，可以在 rayon 的并行任务中共享
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    items: Mutex<Vec<Diagnostic>>,
+    severity: SeverityConfig,
+    // 源码根目录，severity 中的路径相对于它
+    root: Option<PathBuf>,
+    // 各文件中 `-- @allow(...)` 忽略的行与编号
+    allows: Mutex<HashMap<PathBuf, Vec<(usize, Code)>>>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 按 dlua.json 的配置调整严重程度
+    pub fn with_severity(severity: SeverityConfig, root: &Path) -> Self {
+        Diagnostics {
+            severity,
+            root: Some(root.canonicalize().unwrap_or(root.to_path_buf())),
+            ..Self::default()
+        }
+    }
+
+    /// 记录文件中的 `-- @allow(...)`：每条注释作用于所在行与下一行
+    pub fn allow(&self, path: &Path, allowed: Vec<(usize, Code)>) {
+        if allowed.is_empty() {
+            return;
+        }
+        let path = path.canonicalize().unwrap_or(path.to_path_buf());
+        self.allows.lock().unwrap().insert(path, allowed);
+    }
+
+    /// 记录一条诊断，返回它是否被记录（没有被忽略）。
+    /// 只有 [`Code::can_demote`] 的编号能被降级或忽略，其它错误总是保持为错误
+    pub fn push(&self, diagnostic: impl Into<Diagnostic>) -> bool {
+        let mut diagnostic = diagnostic.into();
+        if diagnostic.error.code.can_demote() {
+            if self.is_allowed(&diagnostic) {
+                return false;
+            }
+            let relative = self.relative_path(&diagnostic);
+            match self
+                .severity
+                .level(diagnostic.error.code, relative.as_deref())
+            {
+                Some(Level::Allow) => return false,
+                Some(Level::Warning) => diagnostic.severity = Severity::Warning,
+                Some(Level::Error) => diagnostic.severity = Severity::Error,
+                None => {}
+            }
+        }
+        self.items.lock().unwrap().push(diagnostic);
+        true
+    }
+
+    fn is_allowed(&self, diagnostic: &Diagnostic) -> bool {
+        let Some(location) = &diagnostic.error.location else {
+            return false;
+        };
+        let allows = self.allows.lock().unwrap();
+        allows.get(&location.path).is_some_and(|allowed| {
+            allowed.iter().any(|&(line, code)| {
+                code == diagnostic.error.code
+                    && (line == location.line || line + 1 == location.line)
+            })
+        })
+    }
+
+    /// 诊断所在文件相对于源码根目录的路径，以 / 分隔
+    fn relative_path(&self, diagnostic: &Diagnostic) -> Option<String> {
+        let path = &diagnostic.error.location.as_ref()?.path;
+        let relative = path.strip_prefix(self.root.as_ref()?).ok()?;
+        Some(relative.to_string_lossy().replace('\\', "/"))
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.items
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|d| d.severity == Severity::Error)
+    }
+
+    /// 按文件与位置排序后取出所有诊断，没有位置的排在最后
+    pub fn into_sorted(self) -> Vec<Diagnostic> {
+        let mut items = self.items.into_inner().unwrap();
+        items.sort_by(|a, b| {
+            let key = |d: &Diagnostic| {
+                d.error
+                    .location
+                    .as_ref()
+                    .map(|l| (l.path.clone(), l.line, l.column))
+            };
+            match (key(a), key(b)) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (None, None) => a.error.message.cmp(&b.error.message),
+            }
+        });
+        items
+    }
+}
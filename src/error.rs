@@ -0,0 +1,132 @@
+use std::{fmt, ops::Range, path::PathBuf};
+
+use crate::code::Code;
+
+/// 错误的种类
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// 宏的定义或调用有误
+    Macro,
+    /// 生成的代码不是合法的 Lua
+    Syntax,
+    /// require 无法解析
+    Require,
+    /// 读写文件失败
+    Io,
+    /// 配置有误
+    Config,
+}
+
+impl ErrorKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorKind::Macro => "macro",
+            ErrorKind::Syntax => "syntax",
+            ErrorKind::Require => "require",
+            ErrorKind::Io => "io",
+            ErrorKind::Config => "config",
+        }
+    }
+}
+
+/// 源码中的位置，行列号从 1 开始
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub path: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    // 区间结束处（不含）的行列号
+    pub end_line: usize,
+    pub end_column: usize,
+    // 字节区间
+    pub span: Range<usize>,
+    // 位置所在的一行源码（不含换行），用于渲染
+    pub source_line: String,
+}
+
+/// 附加在诊断上的次要位置，如遮蔽宏的局部变量、宏的定义处
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub location: Location,
+    pub message: String,
+}
+
+/// 展开或构建过程中的错误
+#[derive(Debug, Clone)]
+pub struct Error {
+    pub code: Code,
+    pub kind: ErrorKind,
+    pub message: String,
+    pub location: Option<Location>,
+    // 出错位置所在的宏展开
+    pub macro_name: Option<String>,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    pub fn new(code: Code, message: impl Into<String>) -> Self {
+        Error {
+            code,
+            kind: code.kind(),
+            message: message.into(),
+            location: None,
+            macro_name: None,
+            labels: Vec::new(),
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    pub fn at(mut self, location: Location) -> Self {
+        self.location = Some(location);
+        self
+    }
+
+    pub fn in_macro(mut self, name: impl Into<String>) -> Self {
+        self.macro_name = Some(name.into());
+        self
+    }
+
+    pub fn with_label(mut self, location: Location, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            location,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(location) = &self.location {
+            write!(
+                f,
+                "{}:{}:{}",
+                location.path.display(),
+                location.line,
+                location.column
+            )?;
+            if let Some(name) = &self.macro_name {
+                write!(f, " (in expansion of macro {})", name)?;
+            }
+            write!(f, ": ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,445 @@
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+
+use crate::{
+    Config,
+    code::Code,
+    diagnostics::Diagnostics,
+    error::{Error, Result},
+    project::{File, Project},
+    resolve::SearchPath,
+};
+
+/// `dlua graph` 的输出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    Dot,
+    Json,
+    Mermaid,
+}
+
+impl GraphFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "dot" => Some(GraphFormat::Dot),
+            "json" => Some(GraphFormat::Json),
+            "mermaid" => Some(GraphFormat::Mermaid),
+            _ => None,
+        }
+    }
+}
+
+/// require 指向的模块
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Target {
+    Module(PathBuf),
+    // 在查找路径中不存在的模块名
+    Unresolved(String),
+    // 参数不是字符串常量，记录参数表达式
+    Dynamic(String),
+}
+
+/// 一处 require 或 `-- @use`
+#[derive(Debug, Clone)]
+pub struct Edge {
+    pub from: PathBuf,
+    pub target: Target,
+    // 同一个文件多次引用同一个模块时取第一处
+    pub line: usize,
+    // 只通过 `-- @use` 导入宏，运行时不加载
+    pub via_use: bool,
+}
+
+/// 项目中所有文件之间的 require 关系，包括无法解析与动态的 require
+#[derive(Debug, Clone)]
+pub struct RequireGraph {
+    pub root: PathBuf,
+    pub files: Vec<PathBuf>,
+    pub edges: Vec<Edge>,
+}
+
+impl RequireGraph {
+    /// 分词项目中的所有文件并解析其中的 require，不读写缓存。
+    /// 文件与模块的路径与构建时的 Project 一样来自 Project::lua_files 与 SearchPath，两边可以直接比较
+    pub fn scan(config: &Config) -> Self {
+        let search_path = SearchPath::new(config.require_paths.as_deref().unwrap_or_default());
+        // 格式有误的 `-- @use` 在构建时报告，这里只收集能解析的部分
+        let diagnostics = Diagnostics::new();
+        let mut files = Project::lua_files(&config.entry);
+        files.sort();
+        let mut edges = Vec::new();
+        let mut seen: HashSet<(PathBuf, Target, bool)> = HashSet::new();
+        for path in &files {
+            let Ok(file) = File::read(path, config.dialect) else {
+                continue;
+            };
+            let required_modules = Project::map_dynamic_requires(
+                &config.entry,
+                path,
+                Project::get_required_modules(&file, &diagnostics),
+                &config.dynamic_requires,
+            );
+            for required in required_modules {
+                let target = if required.dynamic {
+                    Target::Dynamic(required.name)
+                } else {
+                    match search_path.resolve(&config.entry, &required.name) {
+                        Some(module) => Target::Module(module),
+                        None => Target::Unresolved(required.name),
+                    }
+                };
+                let via_use = required.items.is_some();
+                // 动态 require 每处单独列出
+                let key = (path.clone(), target.clone(), via_use);
+                if !matches!(target, Target::Dynamic(_)) && !seen.insert(key) {
+                    continue;
+                }
+                edges.push(Edge {
+                    from: path.clone(),
+                    target,
+                    line: file.line_col_from_pos(required.span.start).0,
+                    via_use,
+                });
+            }
+        }
+        RequireGraph {
+            root: config.entry.clone(),
+            files,
+            edges,
+        }
+    }
+
+    /// 从 start 出发能到达的子图
+    pub fn reachable_from(&self, start: &Path) -> Self {
+        let mut reached: HashSet<&Path> = HashSet::from([start]);
+        let mut queue = VecDeque::from([start]);
+        while let Some(path) = queue.pop_front() {
+            for edge in self.edges.iter().filter(|e| e.from == path) {
+                if let Target::Module(module) = &edge.target
+                    && reached.insert(module)
+                {
+                    queue.push_back(module);
+                }
+            }
+        }
+        RequireGraph {
+            root: self.root.clone(),
+            files: self
+                .files
+                .iter()
+                .filter(|p| reached.contains(p.as_path()))
+                .cloned()
+                .collect(),
+            edges: self
+                .edges
+                .iter()
+                .filter(|e| reached.contains(e.from.as_path()))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// 相对于源码根目录、以 / 分隔的路径
+    fn display(&self, path: &Path) -> String {
+        path.strip_prefix(&self.root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+
+    /// 边指向的节点的 id 与显示的名字
+    fn target_node(&self, edge: &Edge) -> (String, String) {
+        match &edge.target {
+            Target::Module(path) => (self.display(path), self.display(path)),
+            Target::Unresolved(name) => (format!("unresolved:{}", name), name.clone()),
+            Target::Dynamic(expression) => (
+                format!("dynamic:{}:{}", self.display(&edge.from), edge.line),
+                expression.clone(),
+            ),
+        }
+    }
+
+    /// Graphviz DOT。无法解析的模块用红色虚线框，动态 require 用橙色，`-- @use` 用虚线
+    pub fn to_dot(&self) -> String {
+        let quote = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut out = String::from("digraph requires {\n    rankdir=LR;\n    node [shape=box];\n");
+        for path in &self.files {
+            out.push_str(&format!("    {};\n", quote(&self.display(path))));
+        }
+        let mut declared: HashSet<String> = HashSet::new();
+        for edge in &self.edges {
+            let (id, label) = self.target_node(edge);
+            let style = match edge.target {
+                Target::Module(_) => None,
+                Target::Unresolved(_) => Some("style=dashed, color=red, fontcolor=red"),
+                Target::Dynamic(_) => Some("shape=note, color=orange, fontcolor=orange"),
+            };
+            if let Some(style) = style
+                && declared.insert(id.clone())
+            {
+                out.push_str(&format!(
+                    "    {} [label={}, {}];\n",
+                    quote(&id),
+                    quote(&label),
+                    style
+                ));
+            }
+            let attributes = match (&edge.target, edge.via_use) {
+                (_, true) => " [style=dashed, label=\"@use\"]",
+                (Target::Unresolved(_), _) => " [color=red]",
+                (Target::Dynamic(_), _) => " [color=orange]",
+                _ => "",
+            };
+            out.push_str(&format!(
+                "    {} -> {}{};\n",
+                quote(&self.display(&edge.from)),
+                quote(&id),
+                attributes
+            ));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// JSON：nodes 的 kind 为 module、unresolved 或 dynamic，edges 的 kind 为 require 或 use
+    pub fn to_json(&self) -> Value {
+        let mut nodes: Vec<Value> = self
+            .files
+            .iter()
+            .map(|path| json!({ "id": self.display(path), "kind": "module" }))
+            .collect();
+        let mut declared: HashSet<String> = HashSet::new();
+        let mut edges = Vec::new();
+        for edge in &self.edges {
+            let (id, label) = self.target_node(edge);
+            match edge.target {
+                Target::Unresolved(_) if declared.insert(id.clone()) => {
+                    nodes.push(json!({ "id": id, "kind": "unresolved", "module": label }));
+                }
+                Target::Dynamic(_) if declared.insert(id.clone()) => {
+                    nodes.push(json!({ "id": id, "kind": "dynamic", "expression": label }));
+                }
+                _ => {}
+            }
+            edges.push(json!({
+                "from": self.display(&edge.from),
+                "to": id,
+                "kind": if edge.via_use { "use" } else { "require" },
+                "line": edge.line,
+            }));
+        }
+        json!({ "nodes": nodes, "edges": edges })
+    }
+
+    /// Mermaid 流程图。节点名中的引号转义为 #quot;
+    pub fn to_mermaid(&self) -> String {
+        let mut ids: HashMap<String, String> = HashMap::new();
+        let mut out = String::from("graph LR\n");
+        let mut node = |out: &mut String, id: &str, label: &str, class: Option<&str>| {
+            if let Some(existing) = ids.get(id) {
+                return existing.clone();
+            }
+            let short = format!("n{}", ids.len());
+            out.push_str(&format!(
+                "    {}[\"{}\"]",
+                short,
+                label.replace('"', "#quot;")
+            ));
+            if let Some(class) = class {
+                out.push_str(&format!(":::{}", class));
+            }
+            out.push('\n');
+            ids.insert(id.to_string(), short.clone());
+            short
+        };
+        for path in &self.files {
+            let name = self.display(path);
+            node(&mut out, &name, &name, None);
+        }
+        let mut links = String::new();
+        for edge in &self.edges {
+            let (id, label) = self.target_node(edge);
+            let class = match edge.target {
+                Target::Module(_) => None,
+                Target::Unresolved(_) => Some("unresolved"),
+                Target::Dynamic(_) => Some("dynamic"),
+            };
+            let from_name = self.display(&edge.from);
+            let from = node(&mut out, &from_name, &from_name, None);
+            let to = node(&mut out, &id, &label, class);
+            let arrow = if edge.via_use { "-. @use .->" } else { "-->" };
+            links.push_str(&format!("    {} {} {}\n", from, arrow, to));
+        }
+        out.push_str(&links);
+        out.push_str("    classDef unresolved stroke:#d33,stroke-dasharray:4 4,color:#d33\n");
+        out.push_str("    classDef dynamic stroke:#e90,color:#e90\n");
+        out
+    }
+}
+
+/// `dlua graph [root] [--format dot|json|mermaid] [--from module]`：生成 require 关系图的文本。
+/// from 可以是模块名，也可以是相对于源码根目录的文件路径，找不到时返回 DL0001 错误
+pub fn run(config: &Config, format: GraphFormat, from: Option<&str>) -> Result<String> {
+    let mut graph = RequireGraph::scan(config);
+    if let Some(from) = from {
+        let search_path = SearchPath::new(config.require_paths.as_deref().unwrap_or_default());
+        let start = search_path.resolve(&config.entry, from).or_else(|| {
+            let path = config.entry.join(from);
+            graph.files.iter().find(|p| **p == path).cloned()
+        });
+        let Some(start) = start else {
+            return Err(Error::new(
+                Code::UnresolvedRequire,
+                format!("module '{}' not found in {}", from, config.entry.display()),
+            ));
+        };
+        graph = graph.reachable_from(&start);
+    }
+    Ok(match format {
+        GraphFormat::Dot => graph.to_dot(),
+        GraphFormat::Json => format!(
+            "{}\n",
+            serde_json::to_string_pretty(&graph.to_json()).unwrap()
+        ),
+        GraphFormat::Mermaid => graph.to_mermaid(),
+    })
+}
+
+/// require 关系图中的一个强连通分量及其中的一个环
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Cycle {
+    // 从分量中路径最小的模块开始的最短环，首尾是同一个模块，如 `[a, b, a]`
+    pub path: Vec<PathBuf>,
+    // 分量中的所有模块，按路径排序
+    pub modules: Vec<PathBuf>,
+    // 分量内不在 path 上的边，按路径排序。为空时 path 就是分量中唯一的环
+    pub others: Vec<(PathBuf, PathBuf)>,
+}
+
+/// require 关系图中的环。不列举所有的环（数量可能随模块数指数增长），
+/// 每个强连通分量只给出一个最短的环、分量中的全部模块与环以外的边；结果按起点排序
+pub fn find_cycles(edges: &HashMap<PathBuf, Vec<PathBuf>>) -> Vec<Cycle> {
+    let mut cycles: Vec<Cycle> = strongly_connected_components(edges)
+        .into_iter()
+        .filter_map(|component| {
+            let path = shortest_cycle(edges, &component)?;
+            let members: HashSet<&PathBuf> = component.iter().copied().collect();
+            let on_path: HashSet<(&PathBuf, &PathBuf)> =
+                path.windows(2).map(|pair| (&pair[0], &pair[1])).collect();
+            // 对同一个模块的多处 require 只算一条边
+            let mut others: Vec<(PathBuf, PathBuf)> = component
+                .iter()
+                .flat_map(|node| {
+                    let deps = edges.get(*node).into_iter().flatten();
+                    deps.filter(|dep| members.contains(dep))
+                        .map(move |dep| (*node, dep))
+                })
+                .filter(|edge| !on_path.contains(edge))
+                .map(|(from, to)| (from.clone(), to.clone()))
+                .collect();
+            others.sort();
+            others.dedup();
+            let mut modules: Vec<PathBuf> = component.into_iter().cloned().collect();
+            modules.sort();
+            Some(Cycle {
+                path,
+                modules,
+                others,
+            })
+        })
+        .collect();
+    cycles.sort();
+    cycles
+}
+
+/// Tarjan 算法求强连通分量。用显式的栈代替递归，几千个文件的长 require 链也不会栈溢出
+fn strongly_connected_components(edges: &HashMap<PathBuf, Vec<PathBuf>>) -> Vec<Vec<&PathBuf>> {
+    let mut nodes: Vec<&PathBuf> = edges.keys().chain(edges.values().flatten()).collect();
+    nodes.sort();
+    nodes.dedup();
+
+    let mut index: HashMap<&PathBuf, usize> = HashMap::new();
+    let mut low: HashMap<&PathBuf, usize> = HashMap::new();
+    let mut on_stack: HashSet<&PathBuf> = HashSet::new();
+    let mut stack: Vec<&PathBuf> = Vec::new();
+    let mut components = Vec::new();
+
+    for root in nodes {
+        if index.contains_key(root) {
+            continue;
+        }
+        // (节点, 下一个要访问的依赖的下标)
+        let mut call_stack: Vec<(&PathBuf, usize)> = vec![(root, 0)];
+        index.insert(root, index.len());
+        low.insert(root, index[root]);
+        stack.push(root);
+        on_stack.insert(root);
+
+        while let Some(top) = call_stack.last_mut() {
+            let (node, next) = (top.0, top.1);
+            top.1 += 1;
+            let deps = edges.get(node).map(Vec::as_slice).unwrap_or_default();
+            if let Some(dep) = deps.get(next) {
+                if !index.contains_key(dep) {
+                    index.insert(dep, index.len());
+                    low.insert(dep, index[dep]);
+                    stack.push(dep);
+                    on_stack.insert(dep);
+                    call_stack.push((dep, 0));
+                } else if on_stack.contains(dep) {
+                    low.insert(node, low[node].min(index[dep]));
+                }
+                continue;
+            }
+            // node 的依赖都已访问完
+            call_stack.pop();
+            if let Some((parent, _)) = call_stack.last() {
+                low.insert(parent, low[parent].min(low[node]));
+            }
+            if low[node] == index[node] {
+                let mut component = Vec::new();
+                while let Some(member) = stack.pop() {
+                    on_stack.remove(member);
+                    component.push(member);
+                    if member == node {
+                        break;
+                    }
+                }
+                components.push(component);
+            }
+        }
+    }
+    components
+}
+
+/// 分量中从最小的模块出发、回到它自身的最短路径；分量不构成环时返回 None
+fn shortest_cycle(
+    edges: &HashMap<PathBuf, Vec<PathBuf>>,
+    component: &[&PathBuf],
+) -> Option<Vec<PathBuf>> {
+    let start = *component.iter().min()?;
+    let members: HashSet<&PathBuf> = component.iter().copied().collect();
+    let mut parent: HashMap<&PathBuf, &PathBuf> = HashMap::new();
+    let mut queue = VecDeque::from([start]);
+    while let Some(node) = queue.pop_front() {
+        for dep in edges.get(node).into_iter().flatten() {
+            if dep == start {
+                // 从 node 沿 parent 回到 start，反转后即 start -> ... -> node -> start
+                let mut cycle = vec![start.clone(), node.clone()];
+                let mut current = node;
+                while let Some(prev) = parent.get(current) {
+                    cycle.push((*prev).clone());
+                    current = prev;
+                }
+                cycle.reverse();
+                return Some(cycle);
+            }
+            if members.contains(dep) && !parent.contains_key(dep) && dep != start {
+                parent.insert(dep, node);
+                queue.push_back(dep);
+            }
+        }
+    }
+    None
+}
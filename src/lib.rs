@@ -0,0 +1,172 @@
+//! dlua：Lua 宏展开工具。
+//!
+//! 可以作为命令行工具使用，也可以在其它 Rust 构建工具中调用：
+//! 用 [`build`] 按 [`Config`] 构建整个项目，或用 [`expand_source`] 展开一段源码。
+//! 出错时返回带位置的 [`Error`]，不会结束进程。
+
+// 错误只在出错时构造一次，带上源码片段与标注比缩小 Result 更重要
+#![allow(clippy::result_large_err)]
+
+// 子命令与日志。log 中的宏以 `$crate::log` 引用，因此对外公开
+pub mod graph;
+pub mod log;
+pub mod resolve;
+pub mod sarif;
+pub mod trace;
+
+pub(crate) mod code;
+pub(crate) mod diagnostics;
+pub(crate) mod error;
+pub(crate) mod macros;
+pub(crate) mod project;
+pub(crate) mod severity;
+pub(crate) mod source_map;
+pub(crate) mod syntax;
+pub(crate) mod token;
+
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+pub use code::Code;
+pub use diagnostics::{Diagnostic, Diagnostics, Severity};
+pub use error::{Error, ErrorKind, Label, Location, Result};
+pub use macros::{Macro, OutputOptions};
+pub use resolve::DynamicRequires;
+pub use severity::{Level, SeverityConfig};
+pub use source_map::{Lookup, Mapping, SourceMap};
+pub use token::Dialect;
+
+use macros::Processor;
+use project::{File, Project, compute_line_starts};
+use token::tokenize;
+
+/// 一次项目构建的配置
+#[derive(Debug, Clone)]
+pub struct Config {
+    // 源码根目录
+    pub entry: PathBuf,
+    // 导出目录
+    pub export_path: PathBuf,
+    // require 的查找路径，默认为根目录
+    pub require_paths: Option<Vec<String>>,
+    // 动态 require 可能加载的模块
+    pub dynamic_requires: DynamicRequires,
+    pub dialect: Dialect,
+    // 忽略缓存，处理所有文件
+    pub full: bool,
+    pub output: OutputOptions,
+    // 按编号与路径调整诊断的严重程度
+    pub severity: SeverityConfig,
+}
+
+impl Config {
+    pub fn new(entry: impl Into<PathBuf>, export_path: impl Into<PathBuf>) -> Self {
+        Config {
+            entry: entry.into(),
+            export_path: export_path.into(),
+            require_paths: None,
+            dynamic_requires: DynamicRequires::default(),
+            dialect: Dialect::default(),
+            full: false,
+            output: OutputOptions::default(),
+            severity: SeverityConfig::default(),
+        }
+    }
+
+    /// 读取 dlua.json 中的配置项
+    pub fn apply_json(&mut self, config: &Value) -> Result<()> {
+        // 可以是路径数组，也可以是一个 package.path 字符串
+        if let Some(paths) = config.get("require_paths") {
+            self.require_paths = match paths {
+                Value::String(path) => Some(vec![path.clone()]),
+                _ => paths.as_array().map(|arr| {
+                    arr.iter()
+                        .filter_map(|v| v.as_str().map(|s| s.to_string()))
+                        .collect()
+                }),
+            };
+            if let Some(paths) = &self.require_paths {
+                resolve::SearchPath::new(paths).check()?;
+            }
+        }
+        if let Some(name) = config.get("dialect").and_then(|v| v.as_str()) {
+            self.dialect = Dialect::from_name(name).ok_or_else(|| {
+                Error::new(
+                    Code::Config,
+                    format!(
+                        "Unknown dialect '{}' in dlua.json, expected one of lua51, luajit, lua54, luau",
+                        name
+                    ),
+                )
+            })?;
+        }
+        // 与命令行的 --strip-empty-modules 任一开启即生效
+        if config.get("strip_empty_modules").and_then(|v| v.as_bool()) == Some(true) {
+            self.output.strip_empty_modules = true;
+        }
+        self.dynamic_requires = DynamicRequires::from_json(config)?;
+        self.severity = SeverityConfig::from_json(config)?;
+        Ok(())
+    }
+}
+
+impl Config {
+    /// 影响输出的配置的哈希，记录在增量构建的缓存中，改变时重新处理所有文件
+    pub fn fingerprint(&self) -> u64 {
+        let options = format!(
+            "{:?}|{:?}|{:?}|{:?}|{}",
+            self.require_paths,
+            self.dialect,
+            self.output,
+            self.dynamic_requires,
+            self.severity.fingerprint_key()
+        );
+        project::fnv1a(options.as_bytes())
+    }
+}
+
+/// 构建整个项目并写入导出目录。所有文件的错误与警告按文件与位置排序：
+/// 没有错误时返回警告；有错误时不写入任何文件，返回包括警告在内的全部诊断
+pub fn build(config: &Config) -> Result<Vec<Diagnostic>, Vec<Diagnostic>> {
+    let diagnostics = Diagnostics::with_severity(config.severity.clone(), &config.entry);
+    match Project::load(config, &diagnostics) {
+        Ok(project) => {
+            let mut processor = Processor::new(project, &diagnostics);
+            processor.expand_all(&config.export_path, &config.output, &diagnostics);
+            // processor 在这里释放，Project 的 Drop 会保存缓存
+        }
+        Err(err) => {
+            diagnostics.push(Error::new(
+                Code::Io,
+                format!("failed to load project {}: {}", config.entry.display(), err),
+            ));
+        }
+    }
+
+    let has_errors = diagnostics.has_errors();
+    let sorted = diagnostics.into_sorted();
+    if has_errors { Err(sorted) } else { Ok(sorted) }
+}
+
+/// 展开一段源码。除源码自身定义的宏之外，macros 中的宏在整段源码中可见
+pub fn expand_source(
+    src: &str,
+    macros: &HashMap<String, Macro>,
+    dialect: Dialect,
+) -> Result<String> {
+    let path = Path::new("<source>");
+    let mut file = File::new(path, tokenize(src, dialect));
+    file.line_starts = compute_line_starts(src);
+    file.source = src.to_string();
+    file.dialect = dialect;
+
+    let mut visible = macros.clone();
+    visible.extend(file.parse_global_macros()?);
+    let global_macro_map = HashMap::from([(path.to_path_buf(), visible)]);
+    file.set_stacks(&[], &global_macro_map);
+    file.expand()?;
+    Ok(file.output)
+}
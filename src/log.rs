@@ -1,11 +1,80 @@
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// 输出的详细程度
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// 只输出错误与警告
+    Quiet,
+    Normal,
+    /// 额外输出各阶段的耗时与文件数
+    Verbose,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        1 => Verbosity::Normal,
+        _ => Verbosity::Verbose,
+    }
+}
+
+/// `--color` 的取值
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorChoice {
+    /// 输出到终端且没有设置 NO_COLOR 时使用颜色
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+}
+
+/// 设置是否使用颜色。日志与诊断都输出到标准错误，因此按标准错误判断是否是终端
+pub fn set_color(choice: ColorChoice) {
+    let enabled = match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                && std::io::stderr().is_terminal()
+        }
+    };
+    colored::control::set_override(enabled);
+}
+
 #[macro_export]
 macro_rules! log_info {
     ($($arg:tt)*) => {
-        {
+        if $crate::log::verbosity() >= $crate::log::Verbosity::Normal {
             use colored::Colorize;
-            println!("{}", format!("[INFO] {}", format!($($arg)*)).green());
+            eprintln!("{}", format!("[INFO] {}", format!($($arg)*)).green());
+        }
+    }
+}
+#[macro_export]
+macro_rules! log_verbose {
+    ($($arg:tt)*) => {
+        if $crate::log::verbosity() >= $crate::log::Verbosity::Verbose {
+            use colored::Colorize;
+            eprintln!("{}", format!("[VERBOSE] {}", format!($($arg)*)).dimmed());
         }
-
     }
 }
 #[macro_export]
@@ -13,7 +82,7 @@ macro_rules! log_warn {
     ($($arg:tt)*) => {
         {
             use colored::Colorize;
-            println!("{}", format!("[WARN] {}", format!($($arg)*)).yellow());
+            eprintln!("{}", format!("[WARN] {}", format!($($arg)*)).yellow());
         }
     }
 }
@@ -22,9 +91,7 @@ macro_rules! log_error {
     ($($arg:tt)*) => {
         {
             use colored::Colorize;
-            use std::process;
-            println!("{}", format!("[ERROR] {}", format!($($arg)*)).red());
-            process::exit(1);
+            eprintln!("{}", format!("[ERROR] {}", format!($($arg)*)).red());
         }
     }
 }
@@ -1,12 +1,18 @@
 use rayon::prelude::*;
 use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
-use crate::project::File;
+use crate::project::{File, Origin, RequireSite, fnv1a};
 use crate::{
-    log_error,
+    code::Code,
+    diagnostics::{Diagnostic, Diagnostics},
+    error::{Error, Location, Result},
+    log_verbose,
     project::Project,
-    token::Token,
+    source_map::SourceMap,
+    syntax,
+    token::{Dialect, Token, TokenWithText, split_interpolation, tokenize},
 };
 
 #[derive(Debug)]
@@ -15,14 +21,45 @@ pub struct Processor {
     project: Project,
 }
 
+/// 一处 require 导入的宏
+#[derive(Clone, Debug)]
+pub struct Import {
+    // require 中模块名的位置，展开到这里时导入
+    pub position: usize,
+    pub macros: HashMap<String, Macro>,
+}
+
+/// 输出阶段的选项，由命令行参数决定
+#[derive(Clone, Debug, Default)]
+pub struct OutputOptions {
+    // 写入前检查生成代码的语法
+    pub validate: bool,
+    // 在每个输出文件旁写入源码映射
+    pub source_map: bool,
+    // 保持输出与源码逐行对应
+    pub preserve_lines: bool,
+    // 不写入输出为空、只被用来导入宏的模块，并去掉对它们的 require
+    pub strip_empty_modules: bool,
+}
+
 // 宏的定义：接受 0-n 个参数，返回字符串
 #[derive(Clone, Debug)]
 pub struct Macro {
     pub params: Vec<String>,
     pub template: String,
+    // 宏名在源码中的位置，用于在诊断中指出定义处
+    pub location: Option<Location>,
 }
 
 impl Macro {
+    pub fn new(params: Vec<String>, template: impl Into<String>) -> Self {
+        Macro {
+            params,
+            template: template.into(),
+            location: None,
+        }
+    }
+
     pub fn expand(&self, args: &[String]) -> String {
         // 按标识符边界替换，避免在其它单词内误替换（例如避免把 "w" 替换到 "scroller_width"）
         if self.params.is_empty() || args.is_empty() {
@@ -72,81 +109,438 @@ impl Macro {
     }
 }
 
+/// 两个宏的定义是否相同：同一处定义，或参数与模板都相同
+fn same_definition(a: &Macro, b: &Macro) -> bool {
+    (a.location.is_some() && a.location == b.location)
+        || (a.params == b.params && a.template == b.template)
+}
+
+impl Error {
+    /// 标出宏的定义处；通过 expand_source 传入的宏没有位置
+    fn with_macro_definition(self, name: &str, macro_obj: &Macro) -> Self {
+        match &macro_obj.location {
+            Some(location) => {
+                self.with_label(location.clone(), format!("macro {} defined here", name))
+            }
+            None => self,
+        }
+    }
+}
+
 impl Processor {
-    pub fn new(project: Project) -> Self {
+    /// 创建处理器并收集所有文件的全局宏，出错的文件记录到 diagnostics 中
+    pub fn new(project: Project, diagnostics: &Diagnostics) -> Self {
         let mut processor = Processor {
             global_macro_map: HashMap::new(),
             project,
         };
-        processor.collect_global_macros();
+        processor.collect_global_macros(diagnostics);
         processor
     }
 
-    fn collect_global_macros(&mut self) {
+    fn collect_global_macros(&mut self, diagnostics: &Diagnostics) {
+        let t0 = std::time::Instant::now();
         let global_macros: HashMap<PathBuf, HashMap<String, Macro>> = self
             .project
             .files
             .par_iter_mut() // 使用并行迭代器
-            .map(|(path, file)| {
-                let macros = file.parse_global_macros();
-                (path.clone(), macros)
+            .filter_map(|(path, file)| match file.parse_global_macros() {
+                Ok(macros) => Some((path.clone(), macros)),
+                Err(err) => {
+                    diagnostics.push(err);
+                    None
+                }
             })
             .collect(); // 收集结果到 HashMap
 
+        log_verbose!(
+            "Collected {} global macros from {} files in {:.2?}",
+            global_macros.values().map(|m| m.len()).sum::<usize>(),
+            global_macros.len(),
+            t0.elapsed()
+        );
         self.global_macro_map = global_macros;
     }
 
-    // 展开所有文件，用户传入：user_level（编译等级），以及宏名称到等级的映射level_map
-    // 不再维护 HashMap，直接写入文件
-    pub fn expand_all_with_levels(
+    /// 每个文件中各个 require 导入的宏
+    fn collect_imports(&self, diagnostics: &Diagnostics) -> HashMap<PathBuf, Vec<Import>> {
+        let mut exports: HashMap<PathBuf, HashMap<String, Macro>> = HashMap::new();
+        let mut imports = HashMap::new();
+        let mut paths: Vec<&PathBuf> = self
+            .project
+            .files
+            .keys()
+            .filter(|path| !self.project.unchanged.contains(*path))
+            .collect();
+        paths.sort();
+        for path in paths {
+            let imported = self.imported_macros(path, &mut exports, diagnostics);
+            imports.insert(path.clone(), imported);
+        }
+        imports
+    }
+
+    /// 文件中每个 require 导入的宏，按 require 的顺序排列，后 require 的优先。
+    /// 两个模块导出同名但不同的宏时给出警告
+    fn imported_macros(
+        &self,
+        path: &Path,
+        exports: &mut HashMap<PathBuf, HashMap<String, Macro>>,
+        diagnostics: &Diagnostics,
+    ) -> Vec<Import> {
+        let mut imports = Vec::new();
+        let mut imported: HashMap<String, (Macro, PathBuf)> = HashMap::new();
+        let file = &self.project.files[path];
+        for site in self.project.require_sites.get(path).into_iter().flatten() {
+            let exported = self.exported_macros(&site.path, exports, &mut Vec::new());
+            let exported = self.selected_macros(site, exported, Some((file, diagnostics)));
+            for (name, macro_obj) in exported.clone() {
+                if let Some((previous, previous_path)) = imported.get(&name)
+                    && previous_path != &site.path
+                    && !same_definition(previous, &macro_obj)
+                {
+                    let mut warning = Error::new(
+                        Code::ImportConflict,
+                        format!(
+                            "macro {} imported from '{}' conflicts with the one imported from '{}'",
+                            name,
+                            self.display_path(&site.path),
+                            self.display_path(previous_path)
+                        ),
+                    )
+                    .at(file.location(site.span.clone()))
+                    .with_note("the macro from the later require is used");
+                    if let Some(location) = &previous.location {
+                        warning = warning.with_label(location.clone(), "previous definition");
+                    }
+                    if let Some(location) = &macro_obj.location {
+                        warning = warning.with_label(location.clone(), "this definition is used");
+                    }
+                    diagnostics.push(Diagnostic::new(warning));
+                }
+                imported.insert(name, (macro_obj, site.path.clone()));
+            }
+            imports.push(Import {
+                position: site.span.start,
+                macros: exported,
+            });
+        }
+        imports
+    }
+
+    /// 模块导出给 require 它的文件的宏：自身的全局宏，加上 `-- @export` 标记的 require 所导出的宏。
+    /// 自身的定义优先于再导出的，后 require 的优先于先 require 的；循环的再导出只取到自身的全局宏
+    fn exported_macros(
+        &self,
+        path: &Path,
+        exports: &mut HashMap<PathBuf, HashMap<String, Macro>>,
+        visiting: &mut Vec<PathBuf>,
+    ) -> HashMap<String, Macro> {
+        if let Some(exported) = exports.get(path) {
+            return exported.clone();
+        }
+        let own = self.global_macro_map.get(path).cloned().unwrap_or_default();
+        if visiting.iter().any(|p| p == path) {
+            return own;
+        }
+        visiting.push(path.to_path_buf());
+        let mut exported: HashMap<String, Macro> = HashMap::new();
+        for site in self.project.require_sites.get(path).into_iter().flatten() {
+            if site.export {
+                let reexported = self.exported_macros(&site.path, exports, visiting);
+                exported.extend(self.selected_macros(site, reexported, None));
+            }
+        }
+        visiting.pop();
+        exported.extend(own);
+        exports.insert(path.to_path_buf(), exported.clone());
+        exported
+    }
+
+    /// `-- @use` 只取列出的宏并按 `as` 重命名，require 取全部。
+    /// 列出的宏不存在时报告到 report 中 site 所在的文件；再导出时不重复报告
+    fn selected_macros(
+        &self,
+        site: &RequireSite,
+        exported: HashMap<String, Macro>,
+        report: Option<(&File, &Diagnostics)>,
+    ) -> HashMap<String, Macro> {
+        let Some(items) = &site.items else {
+            return exported;
+        };
+        let mut selected = HashMap::new();
+        for item in items {
+            if let Some(macro_obj) = exported.get(&item.name) {
+                selected.insert(item.alias.clone(), macro_obj.clone());
+            } else if let Some((file, diagnostics)) = report {
+                let mut available: Vec<&str> = exported.keys().map(String::as_str).collect();
+                available.sort();
+                let mut error = file.error_at(
+                    Code::UseNotFound,
+                    item.span.clone(),
+                    format!(
+                        "macro {} is not exported by module '{}'",
+                        item.name,
+                        self.display_path(&site.path)
+                    ),
+                );
+                error = if available.is_empty() {
+                    error.with_note("the module exports no macros")
+                } else {
+                    error.with_note(format!("the module exports: {}", available.join(", ")))
+                };
+                diagnostics.push(error);
+            }
+        }
+        selected
+    }
+
+    /// 提示信息中的路径，相对于源码根目录
+    fn display_path(&self, path: &Path) -> String {
+        path.strip_prefix(&self.project.root)
+            .unwrap_or(path)
+            .display()
+            .to_string()
+    }
+
+    // 展开所有文件，不再维护 HashMap，直接写入文件
+    // 所有文件的错误都记录到 diagnostics 中；只要有错误，就不写入任何文件，
+    // 并把本次处理的文件从缓存中移除，下次构建时重新处理
+    pub fn expand_all(
         &mut self,
-        user_level: u8,
-        level_map: &HashMap<String, u8>,
-        export_path: &PathBuf,
+        export_path: &Path,
+        options: &OutputOptions,
+        diagnostics: &Diagnostics,
     ) {
         let project_root_path = self.project.root.clone(); // 克隆不可变引用
         let global_macro_map = &self.global_macro_map; // 引用全局宏映射
+        let unchanged = &self.project.unchanged;
+        let imports = self.collect_imports(diagnostics);
 
+        let t0 = std::time::Instant::now();
         self.project
             .files
             .par_iter_mut() // 使用并行迭代器
             .for_each(|(path, file)| {
-                file.set_stacks(&self.project.require_relations, global_macro_map);
-                file.expand(user_level, level_map);
+                // 收集全局宏时已经出错的文件不再展开，只读取宏的文件不需要展开
+                if !global_macro_map.contains_key(path) || unchanged.contains(path) {
+                    return;
+                }
+                let result = expand_file(file, &imports[path], global_macro_map, options);
+                if let Err(err) = result {
+                    diagnostics.push(err);
+                }
+            });
+
+        log_verbose!(
+            "Expanded {} files in {:.2?}",
+            self.project.files.len() - unchanged.len(),
+            t0.elapsed()
+        );
 
-                // 应该使用 export_path 作为根目录，保持相对路径不变
-                let relative_path = path.strip_prefix(&project_root_path).unwrap();
-                let out_path = export_path.join(relative_path).with_extension("lua");
-                if let Some(parent) = out_path.parent() {
-                    std::fs::create_dir_all(parent).unwrap();
+        let stripped = if options.strip_empty_modules && !diagnostics.has_errors() {
+            self.strip_empty_modules(&imports, options, diagnostics)
+        } else {
+            HashSet::new()
+        };
+
+        if !diagnostics.has_errors() {
+            let t1 = std::time::Instant::now();
+            let unchanged = &self.project.unchanged;
+            self.project
+                .files
+                .par_iter() // 使用并行迭代器
+                .filter(|(path, _)| !stripped.contains(*path) && !unchanged.contains(*path))
+                .for_each(|(path, file)| {
+                    // 应该使用 export_path 作为根目录，保持相对路径不变
+                    let relative_path = path.strip_prefix(&project_root_path).unwrap();
+                    let out_path = export_path.join(relative_path).with_extension("lua");
+                    if let Err(err) = write_output(&out_path, file, options) {
+                        diagnostics.push(err);
+                    }
+                });
+            log_verbose!(
+                "Wrote {} files to {:?} in {:.2?}",
+                self.project.files.len() - unchanged.len() - stripped.len(),
+                export_path,
+                t1.elapsed()
+            );
+        }
+        if diagnostics.has_errors() {
+            self.project.discard_processed_files();
+        }
+    }
+
+    /// 找出输出为空且被其它文件 require 的模块，去掉对它们的 require 后重新展开 require 它们的文件；
+    /// 去掉 require 之后变为空的模块也一并处理。返回不再写入的模块
+    fn strip_empty_modules(
+        &mut self,
+        imports: &HashMap<PathBuf, Vec<Import>>,
+        options: &OutputOptions,
+        diagnostics: &Diagnostics,
+    ) -> HashSet<PathBuf> {
+        let t0 = std::time::Instant::now();
+        let mut empty: HashSet<PathBuf> = HashSet::new();
+        loop {
+            let found: Vec<PathBuf> = self
+                .project
+                .reverse_require
+                .iter()
+                .filter(|(path, dependents)| {
+                    !dependents.is_empty()
+                        && !empty.contains(*path)
+                        && module_is_empty(&self.project, path)
+                })
+                .map(|(path, _)| path.clone())
+                .collect();
+            if found.is_empty() {
+                break;
+            }
+            empty.extend(found);
+
+            let require_sites = &self.project.require_sites;
+            let unchanged = &self.project.unchanged;
+            let global_macro_map = &self.global_macro_map;
+            self.project.files.par_iter_mut().for_each(|(path, file)| {
+                if unchanged.contains(path) {
+                    return;
+                }
+                // `-- @use` 不会生成 require，不需要处理
+                let dropped: HashSet<usize> = require_sites
+                    .get(path)
+                    .into_iter()
+                    .flatten()
+                    .filter(|site| site.items.is_none() && empty.contains(&site.path))
+                    .map(|site| site.span.start)
+                    .collect();
+                if dropped.len() == file.dropped_requires.len() {
+                    return;
+                }
+                file.dropped_requires = dropped;
+                let result = expand_file(file, &imports[path], global_macro_map, options);
+                if let Err(err) = result {
+                    diagnostics.push(err);
                 }
-                std::fs::write(out_path, &file.output).unwrap();
             });
+            if diagnostics.has_errors() {
+                break;
+            }
+        }
+
+        // 记录本次处理的文件的输出是否为空，之后只处理 require 它们的文件时仍然可以去掉 require
+        let Project {
+            files,
+            unchanged,
+            cache,
+            ..
+        } = &mut self.project;
+        for (path, file) in files.iter().filter(|(path, _)| !unchanged.contains(*path)) {
+            if let Some(entry) = cache.files.get_mut(path) {
+                entry.empty = is_blank_output(&file.output, file.dialect);
+            }
+        }
+        log_verbose!(
+            "Stripped {} empty modules in {:.2?}",
+            empty.len(),
+            t0.elapsed()
+        );
+        empty
     }
 }
 
 impl File {
     /// 设置作用域栈和遮蔽栈，加载全局宏
+    /// imports 是各个 require 导入的宏，展开到 require 处才加入当前作用域；本文件的全局宏优先于导入的宏
     pub fn set_stacks(
         &mut self,
-        require_relations: &HashMap<PathBuf, Vec<PathBuf>>,
+        imports: &[Import],
         global_macro_map: &HashMap<PathBuf, HashMap<String, Macro>>,
     ) {
+        self.scope_stack.clear();
+        self.shadow_stack.clear();
         self.scope_stack.push(HashMap::new());
-        self.shadow_stack.push(HashSet::new());
-        let global_scope_stack = &mut self.scope_stack[0];
-        if let Some(reqs) = require_relations.get(&self.path) {
-            for dep in reqs {
-                if let Some(m) = global_macro_map.get(dep) {
-                    for (k, v) in m {
-                        global_scope_stack.insert(k.clone(), v.clone());
-                    }
-                }
+        self.shadow_stack.push(HashMap::new());
+        let own = global_macro_map.get(&self.path);
+        if let Some(m) = own {
+            for (k, v) in m {
+                self.scope_stack[0].insert(k.clone(), v.clone());
             }
         }
-        if let Some(m) = global_macro_map.get(&self.path) {
-            for (k, v) in m {
-                global_scope_stack.insert(k.clone(), v.clone());
+        self.pending_imports = imports
+            .iter()
+            .rev()
+            .map(|import| {
+                let mut import = import.clone();
+                import
+                    .macros
+                    .retain(|name, _| own.is_none_or(|m| !m.contains_key(name)));
+                import
+            })
+            .collect();
+    }
+
+    /// 当前的 require 调用是否指向要去掉的空模块，是则返回调用之后的下标，以及结果是否还要被索引或调用。
+    /// 只处理 `require("name")` 的形式
+    fn dropped_require_end(&self) -> Option<(usize, bool)> {
+        if self.dropped_requires.is_empty() {
+            return None;
+        }
+        let open = self.skip_trivia_from(self.parse_index + 1);
+        if self.tokens.get(open)?.kind != Token::LParen {
+            return None;
+        }
+        let name = self.skip_trivia_from(open + 1);
+        let name_token = self.tokens.get(name)?;
+        if name_token.kind != Token::String
+            || !self.dropped_requires.contains(&name_token.span.start)
+        {
+            return None;
+        }
+        let close = self.skip_trivia_from(name + 1);
+        if self.tokens.get(close)?.kind != Token::RParen {
+            return None;
+        }
+        let next = self.skip_trivia_from(close + 1);
+        let suffixed = self.tokens.get(next).is_some_and(|t| match t.kind {
+            Token::Operator => matches!(t.text.as_str(), "." | ":"),
+            Token::LParen | Token::LBracket | Token::LBrace | Token::String => true,
+            _ => false,
+        });
+        Some((close + 1, suffixed))
+    }
+
+    /// 去掉对空模块的 require。require 空模块返回 true：作为语句时整条删除，作为表达式时替换为 true，
+    /// 结果还要被索引或调用时替换为 (true)，运行时的行为不变
+    fn drop_require(&mut self, end: usize, suffixed: bool) {
+        let start = self.parse_index;
+        let replacement = if suffixed {
+            Some("(true)")
+        } else if self.at_statement_start() {
+            None
+        } else {
+            Some("true")
+        };
+        if let Some(replacement) = replacement {
+            let output_start = self.output.len();
+            self.output.push_str(replacement);
+            let span = self.tokens[start].span.start..self.tokens[end - 1].span.end;
+            self.record_origin(output_start, span, None);
+        }
+        self.apply_imports(self.tokens[end - 1].span.start);
+        self.parse_index = end;
+        self.pad_skipped_lines(start);
+    }
+
+    /// 展开到 require 的模块名时，把该模块导出的宏加入当前作用域
+    fn apply_imports(&mut self, position: usize) {
+        while self
+            .pending_imports
+            .last()
+            .is_some_and(|import| import.position <= position)
+        {
+            let import = self.pending_imports.pop().unwrap();
+            if let Some(scope) = self.scope_stack.last_mut() {
+                scope.extend(import.macros);
             }
         }
     }
@@ -155,17 +549,98 @@ impl File {
     fn reset_parse_index(&mut self) {
         self.parse_index = 0;
         self.output.clear();
+        self.origins.clear();
+        self.bracket_stack.clear();
+    }
+
+    /// 记录从 output_start 开始的输出来自源码的 source 区间，相邻的原样输出合并为一段
+    fn record_origin(
+        &mut self,
+        output_start: usize,
+        source: Range<usize>,
+        macro_name: Option<&str>,
+    ) {
+        if macro_name.is_none()
+            && let Some(last) = self.origins.last_mut()
+            && last.macro_name.is_none()
+            && last.source.end == source.start
+            && last.output_start + last.source.len() == output_start
+        {
+            last.source.end = source.end;
+            return;
+        }
+        self.origins.push(Origin {
+            output_start,
+            source,
+            macro_name: macro_name.map(str::to_string),
+        });
     }
 
     fn consume(&mut self) {
-        self.output.push_str(&self.tokens[self.parse_index].text);
+        let token = &self.tokens[self.parse_index];
+        match token.kind {
+            Token::LParen | Token::LBracket | Token::LBrace => {
+                self.bracket_stack
+                    .push((token.kind.clone(), self.scope_stack.len()));
+            }
+            Token::RParen | Token::RBracket | Token::RBrace => {
+                self.bracket_stack.pop();
+            }
+            _ => {}
+        }
+        let output_start = self.output.len();
+        self.output.push_str(&token.text);
+        let span = token.span.clone();
+        self.apply_imports(span.start);
+        self.record_origin(output_start, span, None);
         self.parse_index += 1;
     }
 
-    fn check_eof(&self) {
+    /// 保持行号模式下，为 start 之后被跳过的 token 中的每个换行输出一个空行。
+    /// 每个换行对应源码中被跳过的语句在那一行的部分，源码映射中补齐的行因此指向源码的同一行
+    fn pad_skipped_lines(&mut self, start: usize) {
+        if !self.preserve_lines {
+            return;
+        }
+        let statement_start = self.tokens[start].span.start;
+        let newlines: Vec<usize> = self.tokens[start..self.parse_index]
+            .iter()
+            .flat_map(|t| t.text.match_indices('\n').map(|(i, _)| t.span.start + i))
+            .collect();
+        for pos in newlines {
+            let (line, _) = self.line_col_from_pos(pos);
+            let line_start = self.line_starts[line - 1].max(statement_start);
+            let output_start = self.output.len();
+            self.output.push('\n');
+            self.record_origin(output_start, line_start..pos + 1, None);
+        }
+    }
+
+    /// 保持行号模式下，把多行的宏展开结果合并到一行。注释替换为空格，以免注释掉后面的代码
+    fn collapse_lines(&self, text: &str) -> String {
+        if !self.preserve_lines || !text.contains('\n') {
+            return text.to_string();
+        }
+        tokenize(text, self.dialect)
+            .into_iter()
+            .map(|t| match t.kind {
+                Token::Whitespace if t.text.contains('\n') => " ".to_string(),
+                Token::Comment
+                | Token::MacroComment
+                | Token::AliasComment
+                | Token::UseComment
+                | Token::IfComment
+                | Token::EndIfComment => " ".to_string(),
+                _ => t.text,
+            })
+            .collect()
+    }
+
+    fn check_eof(&self) -> Result<()> {
         if self.parse_index >= self.tokens.len() {
-            log_error!("{}: unexpected end of tokens", self.current_pos());
+            return Err(self.error(Code::MalformedSource, "unexpected end of tokens"));
         }
+        Ok(())
     }
 
     fn consume_whitespace(&mut self) {
@@ -180,13 +655,17 @@ impl File {
         &self.tokens[self.parse_index].kind
     }
 
+    fn current_text(&self) -> &str {
+        &self.tokens[self.parse_index].text
+    }
+
     fn finished(&self) -> bool {
         self.parse_index >= self.tokens.len()
     }
 
     fn enter_scope(&mut self) {
         self.scope_stack.push(HashMap::new());
-        self.shadow_stack.push(HashSet::new());
+        self.shadow_stack.push(HashMap::new());
     }
 
     fn exit_scope(&mut self) {
@@ -195,87 +674,392 @@ impl File {
     }
 
     /// 解析函数参数列表，加入 shadow_stack
-    fn parse_function_args(&mut self, is_global: bool) {
+    fn parse_function_args(&mut self, is_global: bool) -> Result<()> {
         self.consume(); // FunctionKw
         self.consume_whitespace();
-        self.check_eof();
+        self.check_eof()?;
 
         // 可能存在函数名
         if self.current_kind() == &Token::Ident {
+            let mut function_name = self.tokens[self.parse_index].text.clone();
+            let name_span = self.tokens[self.parse_index].span.clone();
+            self.consume(); // 函数名
+            // Luau 中 t:m 会被拆开，需要把后面的部分接回函数名
+            while !self.finished()
+                && self.current_kind() == &Token::Operator
+                && matches!(self.current_text(), "." | ":")
+            {
+                function_name.push_str(&self.tokens[self.parse_index].text);
+                self.consume(); // . 或 :
+                if !self.finished() && self.current_kind() == &Token::Ident {
+                    function_name.push_str(&self.tokens[self.parse_index].text);
+                    self.consume();
+                }
+            }
+
             if is_global {
-                // 全局函数，加入全局作用域
-                self.shadow_stack[0].insert(self.tokens[self.parse_index].text.clone());
+                // 全局函数，相当于对函数名的赋值；t.f、t:m 形式只是字段赋值
+                if root_name(&function_name).len() == function_name.len() {
+                    self.mark_global_assignment(function_name, name_span);
+                }
             } else {
                 // 局部函数，加入当前作用域
                 if let Some(current_shadow) = self.shadow_stack.last_mut() {
-                    current_shadow.insert(self.tokens[self.parse_index].text.clone());
+                    current_shadow.insert(function_name, name_span);
                 } else {
-                    log_error!(
-                        "{}: internal error: shadow_stack is empty when inserting function name",
-                        self.current_pos()
-                    );
+                    return Err(self.error(
+                        Code::Internal,
+                        "internal error: shadow_stack is empty when inserting function name",
+                    ));
                 }
             }
-            self.consume(); // 函数名
         }
 
         // 函数名确定后，进入下一层作用域
         self.scope_stack.push(HashMap::new());
-        self.shadow_stack.push(HashSet::new());
+        self.shadow_stack.push(HashMap::new());
 
         self.consume_whitespace();
-        self.check_eof();
+        self.check_eof()?;
+
+        // Luau 的泛型参数 function f<T>(...)
+        if self.dialect == Dialect::Luau && self.current_text() == "<" {
+            self.consume_angle_group();
+            self.consume_whitespace();
+            self.check_eof()?;
+        }
 
         // 左括号
         if self.current_kind() != &Token::LParen {
-            log_error!(
-                "{}: expected '(' after function name, found {}",
-                self.current_pos(),
-                self.tokens[self.parse_index].text
-            );
+            return Err(self.error(
+                Code::MalformedSource,
+                format!(
+                    "expected '(' after function name, found {}",
+                    self.tokens[self.parse_index].text
+                ),
+            ));
         }
         self.consume(); // LParen
         self.consume_whitespace();
-        self.check_eof();
+        self.check_eof()?;
 
         while !self.finished() && self.current_kind() != &Token::RParen {
-            if self.current_kind() == &Token::Ident {
-                let param_name = self.tokens[self.parse_index].text.clone();
-                if let Some(current_shadow) = self.shadow_stack.last_mut() {
-                    current_shadow.insert(param_name);
-                } else {
-                    log_error!(
-                        "{}: internal error: shadow_stack is empty when inserting function parameter",
-                        self.current_pos()
-                    );
+            match self.current_kind() {
+                Token::Ident => {
+                    let param_name = self.tokens[self.parse_index].text.clone();
+                    let span = self.tokens[self.parse_index].span.clone();
+                    if let Some(current_shadow) = self.shadow_stack.last_mut() {
+                        current_shadow.insert(param_name, span);
+                    } else {
+                        return Err(self.error(Code::Internal, "internal error: shadow_stack is empty when inserting function parameter"));
+                    }
+                    self.consume(); // 参数名
                 }
-                self.consume(); // 参数名
+                Token::Comma => {}
+                // 可变参数 ...
+                _ => self.consume(),
             }
 
             self.consume_whitespace();
-            self.check_eof();
+            self.check_eof()?;
+            self.consume_type_annotation();
 
             if self.current_kind() == &Token::Comma {
                 self.consume(); // Comma
             }
             self.consume_whitespace();
-            self.check_eof();
+            self.check_eof()?;
         }
 
         // 右括号
         if self.current_kind() != &Token::RParen {
-            log_error!(
-                "{}: expected ')' after function parameters, found {}",
-                self.current_pos(),
-                self.tokens[self.parse_index].text
-            );
+            return Err(self.error(
+                Code::MalformedSource,
+                format!(
+                    "expected ')' after function parameters, found {}",
+                    self.tokens[self.parse_index].text
+                ),
+            ));
         }
 
         self.consume(); // RParen
+
+        // Luau 的返回值类型
+        let next = self.skip_trivia_from(self.parse_index);
+        if self.dialect == Dialect::Luau && self.tokens.get(next).is_some_and(|t| t.text == ":") {
+            self.consume_trivia();
+            self.consume_type_annotation();
+        }
+        Ok(())
+    }
+
+    /// 若当前是 Luau 的类型注解 `: type`，原样输出它以及之后的空白
+    fn consume_type_annotation(&mut self) {
+        if self.dialect != Dialect::Luau || self.finished() || self.current_text() != ":" {
+            return;
+        }
+        self.consume(); // :
+        self.consume_type();
+        self.consume_whitespace();
+    }
+
+    /// 原样输出一个 Luau 类型表达式，类型中的名字不会被当作宏
+    fn consume_type(&mut self) {
+        let mut expect_operand = true;
+        loop {
+            // 完整的类型之后只有 | & ? -> 等能延续它，否则空白留给后续的 token 处理
+            let next = self.skip_trivia_from(self.parse_index);
+            let Some(token) = self.tokens.get(next) else {
+                return;
+            };
+            if expect_operand {
+                match token.kind {
+                    Token::Ident | Token::String | Token::Number => {
+                        self.consume_trivia();
+                        self.consume();
+                        if !self.finished() && self.current_text() == "<" {
+                            self.consume_angle_group();
+                        }
+                        expect_operand = false;
+                    }
+                    Token::LParen | Token::LBrace | Token::LBracket => {
+                        self.consume_trivia();
+                        self.consume_raw_group();
+                        expect_operand = false;
+                    }
+                    Token::Operator if token.text == "..." => {
+                        self.consume_trivia();
+                        self.consume();
+                    }
+                    _ => return,
+                }
+            } else {
+                match token.kind {
+                    Token::Operator if matches!(token.text.as_str(), "|" | "&") => {
+                        self.consume_trivia();
+                        self.consume();
+                        expect_operand = true;
+                    }
+                    // 函数类型 (...) -> T
+                    Token::Operator
+                        if token.text == "-"
+                            && self.tokens.get(next + 1).is_some_and(|t| t.text == ">") =>
+                    {
+                        self.consume_trivia();
+                        self.consume(); // -
+                        self.consume(); // >
+                        expect_operand = true;
+                    }
+                    Token::Other if token.text == "?" => {
+                        self.consume_trivia();
+                        self.consume();
+                    }
+                    // typeof(expr)
+                    Token::LParen => {
+                        self.consume_trivia();
+                        self.consume_raw_group();
+                    }
+                    _ => return,
+                }
+            }
+        }
+    }
+
+    /// 原样输出一对括号及其内容，不展开其中的宏
+    fn consume_raw_group(&mut self) {
+        let mut depth = 0usize;
+        while !self.finished() {
+            match self.current_kind() {
+                Token::LParen | Token::LBracket | Token::LBrace => depth += 1,
+                Token::RParen | Token::RBracket | Token::RBrace => {
+                    depth = depth.saturating_sub(1);
+                    if depth == 0 {
+                        self.consume();
+                        return;
+                    }
+                }
+                _ => {}
+            }
+            self.consume();
+        }
+    }
+
+    /// 原样输出 Luau 的泛型参数列表 <...>，其中 >> 会被词法分析成一个运算符
+    fn consume_angle_group(&mut self) {
+        let mut depth = 0usize;
+        while !self.finished() {
+            match self.current_kind() {
+                Token::LParen | Token::LBrace | Token::LBracket => {
+                    self.consume_raw_group();
+                    continue;
+                }
+                Token::Operator => {
+                    let text = self.current_text();
+                    depth += text.matches('<').count();
+                    let closing = text.matches('>').count();
+                    self.consume();
+                    depth = depth.saturating_sub(closing);
+                    if depth == 0 {
+                        return;
+                    }
+                }
+                _ => self.consume(),
+            }
+        }
+    }
+
+    /// Luau 的类型定义语句 `[export] type Name<T> = type`，整体原样输出
+    fn parse_type_statement(&mut self) {
+        if self.current_text() == "export" {
+            self.consume(); // export
+            self.consume_whitespace();
+        }
+        self.consume(); // type
+        self.consume_whitespace();
+        if !self.finished() && self.current_kind() == &Token::Ident {
+            self.consume(); // 类型名
+        }
+        if !self.finished() && self.current_text() == "<" {
+            self.consume_angle_group();
+        }
+        self.consume_whitespace();
+        if !self.finished() && self.current_kind() == &Token::Assign {
+            self.consume(); // =
+            self.consume_type();
+        }
+    }
+
+    /// 当前是否是 Luau 类型定义语句的开头
+    fn at_type_statement(&self) -> bool {
+        if self.dialect != Dialect::Luau || !self.at_statement_start() {
+            return false;
+        }
+        let mut index = self.parse_index;
+        if self.tokens[index].text == "export" {
+            index = self.skip_trivia_from(index + 1);
+            if self.tokens.get(index).is_none_or(|t| t.text != "type") {
+                return false;
+            }
+        } else if self.tokens[index].text != "type" {
+            return false;
+        }
+        let next = self.skip_trivia_from(index + 1);
+        self.tokens
+            .get(next)
+            .is_some_and(|t| t.kind == Token::Ident)
+    }
+
+    /// Luau 插值字符串：{} 中的表达式照常展开宏，其余部分原样输出
+    fn expand_interpolated_string(&mut self) -> Result<()> {
+        let token = self.tokens[self.parse_index].clone();
+        self.parse_index += 1;
+        for (is_expression, range) in split_interpolation(&token.text) {
+            let text = &token.text[range.clone()];
+            if !is_expression {
+                let output_start = self.output.len();
+                self.output.push_str(text);
+                let source = token.span.start + range.start..token.span.start + range.end;
+                self.record_origin(output_start, source, None);
+                continue;
+            }
+            // 把表达式单独分词，借用当前的作用域展开
+            let mut tokens = tokenize(text, self.dialect);
+            let offset = token.span.start + range.start;
+            for t in &mut tokens {
+                t.span = t.span.start + offset..t.span.end + offset;
+            }
+            let saved_tokens = std::mem::replace(&mut self.tokens, tokens);
+            let saved_index = std::mem::replace(&mut self.parse_index, 0);
+            let saved_brackets = std::mem::take(&mut self.bracket_stack);
+            while !self.finished() {
+                self.step()?;
+            }
+            self.tokens = saved_tokens;
+            self.parse_index = saved_index;
+            self.bracket_stack = saved_brackets;
+        }
+        Ok(())
+    }
+
+    /// Luau 的 if 表达式 `if c then a elseif c2 then b else d`，没有 end，也不引入作用域
+    fn parse_if_expression(&mut self) -> Result<()> {
+        self.consume(); // if
+        self.parse_expression()?;
+        while !self.finished() {
+            match self.current_kind() {
+                Token::ThenKw | Token::ElseKw => {
+                    let is_else = self.current_kind() == &Token::ElseKw;
+                    self.consume();
+                    self.parse_expression()?;
+                    if is_else {
+                        return Ok(());
+                    }
+                }
+                Token::ElseIfKw => {
+                    self.consume();
+                    self.parse_expression()?;
+                }
+                _ => return Ok(()),
+            }
+        }
+        Ok(())
     }
 
     /// 解析 for 变量列表，加入 shadow_stack
-    fn parse_for_variables(&mut self) {}
+    /// 循环头部（数值 for 的起止步长、泛型 for 的迭代器表达式）仍在外层作用域求值，
+    /// 循环变量只在 do ... end 的循环体作用域中可见
+    fn parse_for_variables(&mut self) -> Result<()> {
+        self.consume(); // ForKw
+        self.consume_whitespace();
+        self.check_eof()?;
+
+        // 收集循环变量名：数值 for 只有一个，泛型 for 可以有多个
+        let mut var_names: Vec<(String, Range<usize>)> = Vec::new();
+        while !self.finished() && self.current_kind() == &Token::Ident {
+            let token = &self.tokens[self.parse_index];
+            var_names.push((token.text.clone(), token.span.clone()));
+            self.consume(); // 变量名
+            self.consume_whitespace();
+            self.check_eof()?;
+            self.consume_type_annotation();
+
+            if self.current_kind() != &Token::Comma {
+                break;
+            }
+            self.consume(); // Comma
+            self.consume_whitespace();
+            self.check_eof()?;
+        }
+
+        if var_names.is_empty() {
+            return Err(self.error(
+                Code::MalformedSource,
+                format!(
+                    "expected identifier after 'for' keyword, found {}",
+                    self.tokens[self.parse_index].text
+                ),
+            ));
+        }
+
+        // 循环头部中可能存在宏调用，按普通 token 处理
+        while !self.finished() && self.current_kind() != &Token::DoKw {
+            self.step()?;
+        }
+        self.check_eof()?;
+
+        // 进入循环体作用域，再注册循环变量
+        self.enter_scope();
+        self.consume(); // DoKw
+        if let Some(current_shadow) = self.shadow_stack.last_mut() {
+            current_shadow.extend(var_names);
+        } else {
+            return Err(self.error(
+                Code::Internal,
+                "internal error: shadow_stack is empty when inserting for variables",
+            ));
+        }
+        Ok(())
+    }
 
     fn skip(&mut self) {
         self.parse_index += 1;
@@ -289,29 +1073,56 @@ impl File {
         }
     }
 
+    /// 跳过宏参数名之后直到逗号或右括号的内容，如 Luau 的类型注解，以及不能作为参数名的 token
+    fn skip_param_rest(&mut self) -> Result<()> {
+        let mut depth = 0usize;
+        while !self.finished() {
+            match self.current_kind() {
+                Token::Comma | Token::RParen if depth == 0 => return Ok(()),
+                Token::LParen | Token::LBracket | Token::LBrace => depth += 1,
+                Token::RParen | Token::RBracket | Token::RBrace => {
+                    depth = depth.checked_sub(1).ok_or_else(|| {
+                        self.error(
+                            Code::MalformedMacro,
+                            format!(
+                                "unmatched '{}' in macro parameters",
+                                self.tokens[self.parse_index].text
+                            ),
+                        )
+                    })?;
+                }
+                _ => {}
+            }
+            self.skip();
+        }
+        Ok(())
+    }
+
     // 解析宏
-    fn parse_macro_core(&mut self, is_global: bool) {
+    fn parse_macro_core(&mut self, is_global: bool) -> Result<()> {
         // 首先，应该检查这是一个变量宏还是有一个函数宏
-        match self.current_kind() {
-            &Token::Ident => {
+        match *self.current_kind() {
+            Token::Ident => {
                 // 变量宏
                 let macro_name = self.tokens[self.parse_index].text.clone();
+                let name_span = self.tokens[self.parse_index].span.clone();
                 self.skip(); // 跳过宏名称
                 self.skip_whitespace();
-                self.check_eof();
+                self.check_eof()?;
 
                 // 接下来应该是个等号
                 if self.current_kind() != &Token::Assign {
-                    log_error!(
-                        "{}: expected '=' after macro name {}, found {}",
-                        self.current_pos(),
-                        macro_name,
-                        self.tokens[self.parse_index].text
-                    );
+                    return Err(self.error(
+                        Code::MalformedMacro,
+                        format!(
+                            "expected '=' after macro name {}, found {}",
+                            macro_name, self.tokens[self.parse_index].text
+                        ),
+                    ));
                 }
                 self.skip(); // 跳过等号
                 self.skip_whitespace();
-                self.check_eof();
+                self.check_eof()?;
 
                 // 接下来是宏的值
                 let mut template = String::new();
@@ -324,61 +1135,73 @@ impl File {
                 }
 
                 // 不允许在局部作用域定义全局宏
-                if is_global {
-                    if self.scope_stack.len() > 1 {
-                        log_error!(
-                            "{}: trying to define global macro {} inside local scope",
-                            self.current_pos(),
+                if is_global && self.scope_stack.len() > 1 {
+                    return Err(self
+                        .error_at(
+                            Code::GlobalMacroInLocalScope,
+                            name_span,
+                            format!(
+                                "trying to define global macro {} inside local scope",
+                                macro_name
+                            ),
+                        )
+                        .with_help(format!(
+                            "use `local {}` to define a macro visible in this scope only",
                             macro_name
-                        );
-                    }
+                        )));
                 }
 
+                let location = self.location(name_span);
                 if let Some(macro_map) = self.scope_stack.last_mut() {
                     let macro_obj = Macro {
                         params: Vec::new(),
                         template,
+                        location: Some(location),
                     };
                     macro_map.insert(macro_name, macro_obj);
                 } else {
-                    log_error!(
-                        "{}: internal error: scope_stack is empty when inserting local variable macro",
-                        self.current_pos()
-                    );
+                    return Err(self.error(
+                        Code::Internal,
+                        "internal error: scope_stack is empty when inserting local variable macro",
+                    ));
                 }
             }
-            &Token::FunctionKw => {
+            Token::FunctionKw => {
                 // 函数宏
                 self.skip(); // 跳过 FunctionKw
                 self.skip_whitespace();
-                self.check_eof();
+                self.check_eof()?;
 
                 // 接下来应该是宏名称
                 if self.current_kind() != &Token::Ident {
-                    log_error!(
-                        "{}: expected macro name after 'function' keyword, found {}",
-                        self.current_pos(),
-                        self.tokens[self.parse_index].text
-                    );
+                    return Err(self.error(
+                        Code::MalformedMacro,
+                        format!(
+                            "expected macro name after 'function' keyword, found {}",
+                            self.tokens[self.parse_index].text
+                        ),
+                    ));
                 }
 
                 let macro_name = self.tokens[self.parse_index].text.clone();
+                let name_span = self.tokens[self.parse_index].span.clone();
                 self.skip(); // 跳过宏名称
                 self.skip_whitespace();
-                self.check_eof();
+                self.check_eof()?;
 
                 // 左括号
                 if self.current_kind() != &Token::LParen {
-                    log_error!(
-                        "{}: expected '(' after macro name {}, found {}",
-                        self.current_pos(),
-                        macro_name,
-                        self.tokens[self.parse_index].text
-                    );
+                    return Err(self.error(
+                        Code::MalformedMacro,
+                        format!(
+                            "expected '(' after macro name {}, found {}",
+                            macro_name, self.tokens[self.parse_index].text
+                        ),
+                    ));
                 }
                 self.skip(); // 跳过 LParen
                 self.skip_whitespace();
-                self.check_eof();
+                self.check_eof()?;
 
                 // 解析参数列表
                 let mut params: Vec<String> = Vec::new();
@@ -390,25 +1213,31 @@ impl File {
                     }
 
                     self.skip_whitespace();
-                    self.check_eof();
+                    self.check_eof()?;
+                    self.skip_param_rest()?;
+
+                    self.skip_whitespace();
+                    self.check_eof()?;
 
                     if self.current_kind() == &Token::Comma {
                         self.skip(); // 跳过 Comma
                     }
                     self.skip_whitespace();
-                    self.check_eof();
+                    self.check_eof()?;
                 }
                 // 右括号
                 if self.current_kind() != &Token::RParen {
-                    log_error!(
-                        "{}: expected ')' after macro parameters, found {}",
-                        self.current_pos(),
-                        self.tokens[self.parse_index].text
-                    );
+                    return Err(self.error(
+                        Code::MalformedMacro,
+                        format!(
+                            "expected ')' after macro parameters, found {}",
+                            self.tokens[self.parse_index].text
+                        ),
+                    ));
                 }
                 self.skip(); // 跳过 RParen
                 self.skip_whitespace();
-                self.check_eof();
+                self.check_eof()?;
                 // 接下来是宏的模板
                 let mut template = String::new();
                 let mut end_found = false;
@@ -428,64 +1257,86 @@ impl File {
                 }
 
                 if !end_found {
-                    log_error!(
-                        "{}: expected 'end' keyword to terminate macro function definition",
-                        self.current_pos()
-                    );
-                }
-                if is_global {
-                    if self.scope_stack.len() > 1 {
-                        log_error!(
-                            "{}: trying to define global macro {} inside local scope",
-                            self.current_pos(),
+                    return Err(self
+                        .error(
+                            Code::MalformedMacro,
+                            "expected 'end' keyword to terminate macro function definition",
+                        )
+                        .with_label(
+                            self.location(name_span),
+                            format!("macro {} starts here", macro_name),
+                        ));
+                }
+                if is_global && self.scope_stack.len() > 1 {
+                    return Err(self
+                        .error_at(
+                            Code::GlobalMacroInLocalScope,
+                            name_span,
+                            format!(
+                                "trying to define global macro {} inside local scope",
+                                macro_name
+                            ),
+                        )
+                        .with_help(format!(
+                            "use `local {}` to define a macro visible in this scope only",
                             macro_name
-                        );
-                    }
+                        )));
                 }
 
+                // 去掉 return 之后以及 end 之前的空白，展开结果紧贴调用位置
+                let template = template.trim().to_string();
+                let location = self.location(name_span);
                 if let Some(macro_map) = self.scope_stack.last_mut() {
-                    let macro_obj = Macro { params, template };
+                    let macro_obj = Macro {
+                        params,
+                        template,
+                        location: Some(location),
+                    };
                     macro_map.insert(macro_name, macro_obj);
                 } else {
-                    log_error!(
-                        "{}: internal error: scope_stack is empty when inserting local function macro",
-                        self.current_pos()
-                    );
+                    return Err(self.error(
+                        Code::Internal,
+                        "internal error: scope_stack is empty when inserting local function macro",
+                    ));
                 }
             }
             _ => {
-                log_error!(
-                    "{}: expected macro name or 'function' keyword after 'local' keyword, found {}",
-                    self.current_pos(),
-                    self.tokens[self.parse_index].text
-                );
+                return Err(self.error(
+                    Code::MalformedMacro,
+                    format!(
+                        "expected macro name or 'function' keyword after 'local' keyword, found {}",
+                        self.tokens[self.parse_index].text
+                    ),
+                ));
             }
         }
+        Ok(())
     }
 
     // 往前读取，并跳过这个宏定义，不加入宏记录，也不对 output 产生写入
-    fn ignore_macro_core(&mut self) {
+    fn ignore_macro_core(&mut self) -> Result<()> {
         // 首先，应该检查这是一个变量宏还是有一个函数宏
-        match self.current_kind() {
-            &Token::Ident => {
+        match *self.current_kind() {
+            Token::Ident => {
                 // 变量宏
                 let macro_name = self.tokens[self.parse_index].text.clone();
                 self.skip(); // 跳过宏名称
                 self.skip_whitespace();
-                self.check_eof();
+                self.check_eof()?;
 
                 // 接下来应该是个等号
                 if self.current_kind() != &Token::Assign {
-                    log_error!(
-                        "{}: expected '=' after macro name {}, found {}",
-                        self.current_pos(),
-                        macro_name,
-                        self.tokens[self.parse_index].text
-                    );
+                    return Err(self.error(
+                        Code::MalformedMacro,
+                        format!(
+                            "expected '=' after macro name {}, found {}",
+                            macro_name, self.tokens[self.parse_index].text
+                        ),
+                    ));
                 }
                 self.skip(); // 跳过等号
                 self.skip_whitespace();
-                self.check_eof();
+                self.check_eof()?;
 
                 // 接下来是宏的值
                 // let mut template = String::new();
@@ -509,38 +1360,41 @@ impl File {
                 //     );
                 // }
             }
-            &Token::FunctionKw => {
+            Token::FunctionKw => {
                 // 函数宏
                 self.skip(); // 跳过 FunctionKw
                 self.skip_whitespace();
-                self.check_eof();
+                self.check_eof()?;
 
                 // 接下来应该是宏名称
                 if self.current_kind() != &Token::Ident {
-                    log_error!(
-                        "{}: expected macro name after 'function' keyword, found {}",
-                        self.current_pos(),
-                        self.tokens[self.parse_index].text
-                    );
+                    return Err(self.error(
+                        Code::MalformedMacro,
+                        format!(
+                            "expected macro name after 'function' keyword, found {}",
+                            self.tokens[self.parse_index].text
+                        ),
+                    ));
                 }
 
                 let macro_name = self.tokens[self.parse_index].text.clone();
                 self.skip(); // 跳过宏名称
                 self.skip_whitespace();
-                self.check_eof();
+                self.check_eof()?;
 
                 // 左括号
                 if self.current_kind() != &Token::LParen {
-                    log_error!(
-                        "{}: expected '(' after macro name {}, found {}",
-                        self.current_pos(),
-                        macro_name,
-                        self.tokens[self.parse_index].text
-                    );
+                    return Err(self.error(
+                        Code::MalformedMacro,
+                        format!(
+                            "expected '(' after macro name {}, found {}",
+                            macro_name, self.tokens[self.parse_index].text
+                        ),
+                    ));
                 }
                 self.skip(); // 跳过 LParen
                 self.skip_whitespace();
-                self.check_eof();
+                self.check_eof()?;
 
                 // 解析参数列表
                 // let mut params: Vec<String> = Vec::new();
@@ -552,25 +1406,31 @@ impl File {
                     }
 
                     self.skip_whitespace();
-                    self.check_eof();
+                    self.check_eof()?;
+                    self.skip_param_rest()?;
+
+                    self.skip_whitespace();
+                    self.check_eof()?;
 
                     if self.current_kind() == &Token::Comma {
                         self.skip(); // 跳过 Comma
                     }
                     self.skip_whitespace();
-                    self.check_eof();
+                    self.check_eof()?;
                 }
                 // 右括号
                 if self.current_kind() != &Token::RParen {
-                    log_error!(
-                        "{}: expected ')' after macro parameters, found {}",
-                        self.current_pos(),
-                        self.tokens[self.parse_index].text
-                    );
+                    return Err(self.error(
+                        Code::MalformedMacro,
+                        format!(
+                            "expected ')' after macro parameters, found {}",
+                            self.tokens[self.parse_index].text
+                        ),
+                    ));
                 }
                 self.skip(); // 跳过 RParen
                 self.skip_whitespace();
-                self.check_eof();
+                self.check_eof()?;
                 // 接下来是宏的模板
                 // let mut template = String::new();
                 let mut end_found = false;
@@ -590,10 +1450,10 @@ impl File {
                 }
 
                 if !end_found {
-                    log_error!(
-                        "{}: expected 'end' keyword to terminate macro function definition",
-                        self.current_pos()
-                    );
+                    return Err(self.error(
+                        Code::MalformedMacro,
+                        "expected 'end' keyword to terminate macro function definition",
+                    ));
                 }
                 // if let Some(macro_map) = self.scope_stack.last_mut() {
                 //     let macro_obj = Macro { params, template };
@@ -606,128 +1466,140 @@ impl File {
                 // }
             }
             _ => {
-                log_error!(
-                    "{}: expected macro name or 'function' keyword after 'local' keyword, found {}",
-                    self.current_pos(),
-                    self.tokens[self.parse_index].text
-                );
+                return Err(self.error(
+                    Code::MalformedMacro,
+                    format!(
+                        "expected macro name or 'function' keyword after 'local' keyword, found {}",
+                        self.tokens[self.parse_index].text
+                    ),
+                ));
             }
         }
+        Ok(())
     }
 
     /// 解析宏定义
     /// is_global: 尝试解析的是否是全局宏
-    fn parse_local_macro(&mut self) {
+    fn parse_local_macro(&mut self) -> Result<()> {
         // 跳过 Token::MacroComment
         self.skip();
 
         // 跳过宏注释后的空白
         self.skip_whitespace();
-        self.check_eof();
+        self.check_eof()?;
 
         // 现在可以检查宏是全局的还是局部的。如果是局部的，当前必然有 current_kind() == Token::LocalKw
         if self.current_kind() != &Token::LocalKw {
-            self.ignore_macro_core();
-            return;
+            self.ignore_macro_core()?;
+            return Ok(());
         }
         self.skip(); // 跳过 Token::LocalKw
         self.skip_whitespace();
-        self.check_eof();
+        self.check_eof()?;
 
         // 接下来就是宏的解析核心了，调用 parse_macro_core
-        self.parse_macro_core(false);
+        self.parse_macro_core(false)?;
+        Ok(())
     }
 
-    fn parse_global_macro(&mut self) {
+    fn parse_global_macro(&mut self) -> Result<()> {
         self.skip(); // 跳过 Token::MacroComment
         self.skip_whitespace();
-        self.check_eof();
+        self.check_eof()?;
 
         // 现在可以检查宏是全局的还是局部的。
         if self.current_kind() == &Token::LocalKw {
             self.skip();
             self.skip_whitespace();
-            self.check_eof();
-            self.ignore_macro_core();
-            return;
+            self.check_eof()?;
+            self.ignore_macro_core()?;
+            return Ok(());
         }
         // 接下来就是宏的解析核心了，调用 parse_macro_core
-        self.parse_macro_core(true);
+        self.parse_macro_core(true)?;
+        Ok(())
     }
 
-    pub fn parse_global_macros(&mut self) -> HashMap<String, Macro> {
+    pub fn parse_global_macros(&mut self) -> Result<HashMap<String, Macro>> {
         self.reset_parse_index();
         self.scope_stack.push(HashMap::new());
         while !self.finished() {
             match self.current_kind() {
                 &Token::MacroComment => {
-                    self.parse_global_macro();
+                    self.parse_global_macro()?;
                 }
                 _ => {
                     self.skip();
                 }
             }
         }
-        self.scope_stack.pop().unwrap()
+        Ok(self.scope_stack.pop().unwrap())
     }
 
-    fn parse_alias(&mut self) {
+    fn parse_alias(&mut self) -> Result<()> {
         self.skip(); // 跳过 Token::AliasComment
         self.skip_whitespace();
-        self.check_eof();
+        self.check_eof()?;
 
         // 接下来必须是 Token::LocalKw，因为我们禁用了全局的 alias
         if self.current_kind() != &Token::LocalKw {
-            log_error!(
-                "{}: expected 'local' keyword after alias comment, found {}",
-                self.current_pos(),
-                self.tokens[self.parse_index].text
-            );
+            return Err(self.error(
+                Code::MalformedAlias,
+                format!(
+                    "expected 'local' keyword after alias comment, found {}",
+                    self.tokens[self.parse_index].text
+                ),
+            ));
         }
 
         self.skip(); // 跳过 Token::LocalKw
         self.skip_whitespace();
-        self.check_eof();
+        self.check_eof()?;
 
         // 现在应该是 Token::Ident，表示别名的名称
         if self.current_kind() != &Token::Ident {
-            log_error!(
-                "{}: expected identifier after 'local' keyword in alias comment, found {}",
-                self.current_pos(),
-                self.tokens[self.parse_index].text
-            );
+            return Err(self.error(
+                Code::MalformedAlias,
+                format!(
+                    "expected identifier after 'local' keyword in alias comment, found {}",
+                    self.tokens[self.parse_index].text
+                ),
+            ));
         }
 
         let alias_name = self.tokens[self.parse_index].text.clone();
         self.skip(); // 跳过别名名称
         self.skip_whitespace();
-        self.check_eof();
+        self.check_eof()?;
 
         // 接下来应该是 Token::Assign
         if self.current_kind() != &Token::Assign {
-            log_error!(
-                "{}: expected '=' after alias name {}, found {}",
-                self.current_pos(),
-                alias_name,
-                self.tokens[self.parse_index].text
-            );
+            return Err(self.error(
+                Code::MalformedAlias,
+                format!(
+                    "expected '=' after alias name {}, found {}",
+                    alias_name, self.tokens[self.parse_index].text
+                ),
+            ));
         }
 
         self.skip(); // 跳过 '='
         self.skip_whitespace();
-        self.check_eof();
+        self.check_eof()?;
 
         // 现在应该是 Token::Ident，表示被别名的宏名称
         if self.current_kind() != &Token::Ident {
-            log_error!(
-                "{}: expected identifier after '=' in alias comment for alias {}, found {}",
-                self.current_pos(),
-                alias_name,
-                self.tokens[self.parse_index].text
-            );
+            return Err(self.error(
+                Code::MalformedAlias,
+                format!(
+                    "expected identifier after '=' in alias comment for alias {}, found {}",
+                    alias_name, self.tokens[self.parse_index].text
+                ),
+            ));
         }
 
         let target_name = self.tokens[self.parse_index].text.clone();
+        let target_span = self.tokens[self.parse_index].span.clone();
         self.skip(); // 跳过被别名的宏名称
         self.skip_whitespace();
         // 查找被别名的宏定义
@@ -739,13 +1611,23 @@ impl File {
                 // 找到后复制一份
                 // 再在 shadow_stack 中检查是否被遮蔽
                 for k in (j..stack_size).rev() {
-                    if self.shadow_stack[k].contains(&target_name) {
-                        log_error!(
-                            "{}: macro {} is shadowed in current scope, cannot create alias {}",
-                            self.current_pos(),
-                            target_name,
-                            alias_name
-                        );
+                    if let Some(shadow_span) = self.shadow_stack[k].get(&target_name) {
+                        return Err(self
+                            .error_at(
+                                Code::AliasShadowed,
+                                target_span,
+                                format!(
+                                    "macro {} is shadowed in current scope, cannot create alias {}",
+                                    target_name, alias_name
+                                ),
+                            )
+                            .with_label(
+                                self.location(shadow_span.clone()),
+                                format!("{} is shadowed here", target_name),
+                            )
+                            .with_help(
+                                "create the alias before the shadowing declaration, or rename it",
+                            ));
                     }
                 }
                 macro_obj_opt = Some(macro_obj.clone());
@@ -758,223 +1640,861 @@ impl File {
             if let Some(current_scope) = self.scope_stack.last_mut() {
                 current_scope.insert(alias_name, macro_obj);
             } else {
-                log_error!(
-                    "{}: internal error: scope_stack is empty when inserting alias macro",
-                    self.current_pos()
-                );
+                return Err(self.error(
+                    Code::Internal,
+                    "internal error: scope_stack is empty when inserting alias macro",
+                ));
             }
         } else {
-            log_error!(
-                "{}: macro {} not found for alias {}",
-                self.current_pos(),
-                target_name,
-                alias_name
-            );
+            return Err(self.error_at(
+                Code::AliasNotFound,
+                target_span,
+                format!("macro {} not found for alias {}", target_name, alias_name),
+            ));
         }
+        Ok(())
     }
 
     /// 解析局部变量声明，加入 shadow_stack
-    fn parse_local(&mut self) {
+    /// 支持名字列表 `local a, b = ...` 以及 Lua 5.4 的属性 `local a <const>, b <close> = ...`
+    /// 局部变量的作用域从声明语句之后开始，因此初始化表达式中的同名标识符仍指向外层（可以是宏）
+    fn parse_local(&mut self) -> Result<()> {
         self.consume(); // 跳过 Token::LocalKw
         self.consume_whitespace();
-        self.check_eof();
+        self.check_eof()?;
 
         if self.current_kind() == &Token::FunctionKw {
-            self.parse_function_args(false);
-            return;
+            self.parse_function_args(false)?;
+            return Ok(());
         }
 
-        if self.current_kind() != &Token::Ident {
-            log_error!(
-                "{}: expected identifier after 'local' keyword, found {}",
-                self.current_pos(),
-                self.tokens[self.parse_index].text
-            );
+        // 收集局部变量名
+        let mut var_names: Vec<(String, Range<usize>)> = Vec::new();
+        loop {
+            if self.current_kind() != &Token::Ident {
+                return Err(self.error(
+                    Code::MalformedSource,
+                    format!(
+                        "expected identifier after 'local' keyword, found {}",
+                        self.tokens[self.parse_index].text
+                    ),
+                ));
+            }
+            let token = &self.tokens[self.parse_index];
+            var_names.push((token.text.clone(), token.span.clone()));
+            self.consume(); // 变量名
+            self.consume_whitespace();
+            self.consume_type_annotation();
+            if self.finished() {
+                break;
+            }
+
+            // 属性：<const> 或 <close>
+            if self.current_text() == "<" {
+                self.parse_attrib()?;
+                self.consume_whitespace();
+                if self.finished() {
+                    break;
+                }
+            }
+
+            if self.current_kind() != &Token::Comma {
+                break;
+            }
+            self.consume(); // Comma
+            self.consume_whitespace();
+            self.check_eof()?;
+        }
+
+        // 初始化表达式在外层作用域中求值
+        if !self.finished() && self.current_kind() == &Token::Assign {
+            self.consume(); // Assign
+            self.parse_expression_list()?;
         }
 
-        // 收集局部变量名
-        let var_name = self.tokens[self.parse_index].text.clone();
         if let Some(current_shadow) = self.shadow_stack.last_mut() {
-            current_shadow.insert(var_name);
+            current_shadow.extend(var_names);
         } else {
-            log_error!(
-                "{}: internal error: shadow_stack is empty when inserting local variable",
-                self.current_pos()
-            );
+            return Err(self.error(
+                Code::Internal,
+                "internal error: shadow_stack is empty when inserting local variable",
+            ));
         }
-        self.consume(); // 变量名
+        Ok(())
     }
 
-    /// 解析标识符，尝试作为宏调用或普通标识符处理
-    fn parse_ident(&mut self) {
-        let name = self.tokens[self.parse_index].text.clone();
-        let current_parse_index = self.parse_index;
+    /// 解析局部变量的属性 `<name>`，原样输出
+    fn parse_attrib(&mut self) -> Result<()> {
+        self.consume(); // <
+        self.consume_whitespace();
+        self.check_eof()?;
+        if self.current_kind() != &Token::Ident {
+            return Err(self.error(
+                Code::MalformedSource,
+                format!(
+                    "expected attribute name after '<', found {}",
+                    self.tokens[self.parse_index].text
+                ),
+            ));
+        }
+        self.consume(); // 属性名
+        self.consume_whitespace();
+        self.check_eof()?;
+        if self.current_text() != ">" {
+            return Err(self.error(
+                Code::MalformedSource,
+                format!(
+                    "expected '>' after attribute name, found {}",
+                    self.tokens[self.parse_index].text
+                ),
+            ));
+        }
+        self.consume(); // >
+        Ok(())
+    }
 
-        // 先检查是在给这个 ident 赋值，还是说使用它
-        self.skip();
-        self.skip_whitespace();
-        self.check_eof();
+    /// 上一个非空白、非注释的 token
+    fn prev_significant(&self) -> Option<&TokenWithText> {
+        self.significant_before(self.parse_index).map(|(_, t)| t)
+    }
 
-        // 如果是赋值，此时应该是 Token::Assign
-        if self.current_kind() == &Token::Assign {
-            // 加到全局变量中
-            self.shadow_stack[0].insert(name);
-            self.parse_index = current_parse_index; // 回到 ident 位置
-            self.consume(); // ident
-            self.consume_whitespace();
-            self.consume();
-            return;
+    /// index 之前最近的非空白、非注释的 token 及其下标
+    fn significant_before(&self, index: usize) -> Option<(usize, &TokenWithText)> {
+        self.tokens[..index]
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, t)| !matches!(t.kind, Token::Whitespace | Token::Comment))
+    }
+
+    /// 当前标识符是否是字段名（`.`、`:` 之后）或 goto 标签名，这些位置的名字不可能是宏
+    fn at_field_or_label_name(&self) -> bool {
+        match self.significant_before(self.parse_index) {
+            Some((index, prev)) => match prev.kind {
+                Token::Operator if self.closes_label(index) => false,
+                Token::Operator => matches!(prev.text.as_str(), "." | ":" | "::"),
+                Token::Ident => prev.text == "goto",
+                _ => false,
+            },
+            None => false,
         }
+    }
+
+    /// index 处的 token 是否是标签 `::name::` 的结尾。
+    /// 标签之后是新的语句，`::V:: V = 1` 中的第二个 V 不是标签名。
+    /// 分词时 `name::` 会被拆成 `name:` 与 `:`，因此结尾也可能是紧跟在 `name:` 之后的 `:`
+    fn closes_label(&self, index: usize) -> bool {
+        let close = &self.tokens[index];
+        let Some((name_index, name)) = self.significant_before(index) else {
+            return false;
+        };
+        let name_ok = match close.text.as_str() {
+            "::" => name.kind == Token::Ident && !name.text.ends_with(':'),
+            ":" => {
+                name.kind == Token::Ident
+                    && name.text.ends_with(':')
+                    && name.span.end == close.span.start
+            }
+            _ => false,
+        };
+        name_ok
+            && matches!(
+                self.significant_before(name_index),
+                Some((_, open)) if open.kind == Token::Operator && open.text == "::"
+            )
+    }
 
-        // 否则，尝试进行宏展开。
+    /// 当前位置是否是表构造器 `{ name = value }` 中的键
+    fn at_table_key(&self) -> bool {
+        let in_constructor = matches!(
+            self.bracket_stack.last(),
+            Some((Token::LBrace, depth)) if *depth == self.scope_stack.len()
+        );
+        in_constructor
+            && matches!(
+                self.prev_significant().map(|t| &t.kind),
+                Some(Token::LBrace | Token::Comma | Token::Semicolon)
+            )
+    }
+
+    /// 查找当前可见的宏，被遮蔽的宏视为不存在
+    fn lookup_macro(&self, name: &str) -> Option<Macro> {
         let stack_size = self.scope_stack.len();
-        let mut macro_obj_opt: Option<Macro> = None;
         for j in (0..stack_size).rev() {
-            if let Some(macro_obj) = self.scope_stack[j].get(&name) {
-                macro_obj_opt = Some(macro_obj.clone());
+            if let Some(macro_obj) = self.scope_stack[j].get(name) {
                 // 再在 shadow_stack 中检查是否被遮蔽
                 for k in (j..stack_size).rev() {
-                    if self.shadow_stack[k].contains(&name) {
-                        macro_obj_opt = None;
-                        break;
+                    if self.shadow_stack[k].contains_key(name) {
+                        return None;
                     }
                 }
-                break;
+                return Some(macro_obj.clone());
+            }
+        }
+        None
+    }
+
+    /// 从 index 开始跳过空白与注释，返回第一个有意义的 token 的下标
+    fn skip_trivia_from(&self, mut index: usize) -> usize {
+        while index < self.tokens.len()
+            && matches!(self.tokens[index].kind, Token::Whitespace | Token::Comment)
+        {
+            index += 1;
+        }
+        index
+    }
+
+    /// 从开括号 index 开始跳过一对括号，返回闭括号之后的下标
+    fn skip_group_from(&self, mut index: usize) -> Option<usize> {
+        let mut depth = 0usize;
+        while index < self.tokens.len() {
+            match self.tokens[index].kind {
+                Token::LParen | Token::LBracket | Token::LBrace => depth += 1,
+                Token::RParen | Token::RBracket | Token::RBrace => {
+                    depth = depth.saturating_sub(1);
+                    if depth == 0 {
+                        return Some(index + 1);
+                    }
+                }
+                _ => {}
+            }
+            index += 1;
+        }
+        None
+    }
+
+    /// 当前位置是否可能是一条语句的开头：不在当前作用域的括号内，且不紧跟在需要操作数的 token 之后
+    fn at_statement_start(&self) -> bool {
+        if matches!(self.bracket_stack.last(), Some((_, depth)) if *depth == self.scope_stack.len())
+        {
+            return false;
+        }
+        // 标签 `::name::` 之后是新的语句
+        if let Some((index, prev)) = self.significant_before(self.parse_index)
+            && prev.kind == Token::Operator
+            && self.closes_label(index)
+        {
+            return true;
+        }
+        !matches!(
+            self.prev_significant().map(|t| &t.kind),
+            Some(
+                Token::Operator
+                    | Token::Eq
+                    | Token::Assign
+                    | Token::CompoundAssign
+                    | Token::Comma
+                    | Token::AndKw
+                    | Token::OrKw
+                    | Token::NotKw
+                    | Token::ReturnKw
+                    | Token::InKw
+                    | Token::IfKw
+                    | Token::ElseIfKw
+                    | Token::WhileKw
+                    | Token::UntilKw
+            )
+        )
+    }
+
+    /// 检查从当前标识符开始的是否是赋值语句 `target {, target} = ...`（或 Luau 的 `target += ...`）。
+    /// 成功时返回每个赋值目标的起始下标（以及它是否只是一个名字），和等号的下标。
+    fn scan_assignment(&self) -> Option<(Vec<(usize, bool)>, usize)> {
+        if !self.at_statement_start() {
+            return None;
+        }
+        let mut index = self.parse_index;
+        let mut targets: Vec<(usize, bool)> = Vec::new();
+        loop {
+            // 赋值目标以名字开头，后面可以跟下标、字段访问和调用
+            let token = self.tokens.get(index)?;
+            if token.kind != Token::Ident {
+                return None;
+            }
+            let start = index;
+            let mut is_name = root_name(&token.text).len() == token.text.len();
+            index += 1;
+            loop {
+                index = self.skip_trivia_from(index);
+                let token = self.tokens.get(index)?;
+                match token.kind {
+                    Token::LParen | Token::LBracket | Token::LBrace => {
+                        index = self.skip_group_from(index)?;
+                    }
+                    Token::String => index += 1,
+                    Token::Operator if token.text == "." => {
+                        index = self.skip_trivia_from(index + 1);
+                        if self.tokens.get(index)?.kind != Token::Ident {
+                            return None;
+                        }
+                        index += 1;
+                    }
+                    _ => break,
+                }
+                is_name = false;
+            }
+            targets.push((start, is_name));
+
+            match self.tokens.get(index)?.kind {
+                Token::Comma => index = self.skip_trivia_from(index + 1),
+                Token::Assign | Token::CompoundAssign => return Some((targets, index)),
+                _ => return None,
             }
         }
+    }
+
+    /// 记录对全局名字的赋值：之后同名的宏被遮蔽。对已有局部变量的赋值不影响全局
+    fn mark_global_assignment(&mut self, name: String, span: Range<usize>) {
+        if self.shadow_stack[1..]
+            .iter()
+            .any(|shadow| shadow.contains_key(&name))
+        {
+            return;
+        }
+        self.shadow_stack[0].insert(name, span);
+    }
+
+    /// 解析标识符，尝试作为宏调用或普通标识符处理
+    fn parse_ident(&mut self) -> Result<()> {
+        // 字段名、标签名，以及表构造器中的键只是名字，既不展开也不遮蔽宏
+        let next = self.skip_trivia_from(self.parse_index + 1);
+        let before_assign = self
+            .tokens
+            .get(next)
+            .is_some_and(|t| t.kind == Token::Assign);
+        if self.at_field_or_label_name() || (before_assign && self.at_table_key()) {
+            self.consume(); // ident
+            self.consume_whitespace();
+            return Ok(());
+        }
+
+        if let Some((targets, assign_index)) = self.scan_assignment() {
+            self.parse_assignment(&targets, assign_index)?;
+            return Ok(());
+        }
+
+        self.expand_ident()?;
+        Ok(())
+    }
+
+    /// 解析赋值语句。直接对名字赋值时名字原样输出，字段和下标赋值中的宏照常展开；
+    /// 右侧表达式求值之后，被赋值的全局名字才遮蔽同名的宏
+    fn parse_assignment(&mut self, targets: &[(usize, bool)], assign_index: usize) -> Result<()> {
+        let mut assigned_names: Vec<(String, Range<usize>)> = Vec::new();
+        while !self.finished() && self.parse_index < assign_index {
+            match targets.iter().find(|(start, _)| *start == self.parse_index) {
+                Some((_, true)) => {
+                    let token = &self.tokens[self.parse_index];
+                    assigned_names.push((token.text.clone(), token.span.clone()));
+                    self.consume(); // 名字
+                }
+                Some((_, false)) => self.expand_ident()?,
+                None => self.step()?,
+            }
+        }
+        if self.parse_index == assign_index {
+            self.consume(); // = 或 Luau 的复合赋值
+            self.parse_expression_list()?;
+        }
+        for (name, span) in assigned_names {
+            self.mark_global_assignment(name, span);
+        }
+        Ok(())
+    }
+
+    /// 把当前标识符当作对值的使用：是宏则展开，否则原样输出
+    /// 标识符可能是 a.b:c 这样的索引链，宏只按链首的名字查找
+    fn expand_ident(&mut self) -> Result<()> {
+        let name = self.tokens[self.parse_index].text.clone();
+        let current_parse_index = self.parse_index;
+
+        let root = root_name(&name);
+        let macro_obj_opt = self.lookup_macro(root);
         // 是宏调用
         if let Some(macro_obj) = macro_obj_opt {
-            // 首先考虑常量宏
+            // 首先考虑常量宏，允许在其后继续索引，如 ITER.x
             if macro_obj.params.is_empty() {
-                self.parse_index = current_parse_index; // 回到 ident 位置
+                let output_start = self.output.len();
                 self.output.push_str(&macro_obj.expand(&[]));
+                self.output.push_str(&name[root.len()..]);
+                let span = self.tokens[current_parse_index].span.clone();
+                self.record_origin(output_start, span, Some(root));
                 self.skip(); // 跳过 ident
                 self.consume_whitespace();
-                return;
+                return Ok(());
             }
-            // 否则是函数宏调用，检查下一个非空白 token 是否为 '('
-            self.skip();
-            self.consume_whitespace();
-            self.check_eof();
-            if self.current_kind() != &Token::LParen {
-                log_error!(
-                    "{}: macro {} expects {} arguments, but got 0",
-                    self.current_pos(),
-                    name,
-                    macro_obj.params.len()
-                );
+            // 函数宏不能被索引，F.x 中的 F 不是宏调用
+            if root.len() == name.len() {
+                self.skip(); // 跳过 ident
+                self.skip_whitespace();
+                let call_start = self.tokens[current_parse_index].span.start;
+                self.expand_macro_call(&name, &macro_obj, call_start)?;
+                // 跨行的调用展开在调用所在的行，之后补齐被跳过的行
+                self.pad_skipped_lines(current_parse_index);
+                return Ok(());
             }
-            // 解析参数
-            let mut args: Vec<String> = Vec::new();
-            let mut current_arg = String::new();
-            let mut paren_level = 0;
-            while !self.finished() {
-                let tk = &self.tokens[self.parse_index];
-                if tk.kind == Token::RParen && paren_level == 0 {
+        }
+
+        // 不是宏调用
+        self.parse_index = current_parse_index;
+        self.consume(); // ident
+        self.consume_whitespace();
+        Ok(())
+    }
+
+    /// 展开函数宏调用，此时 parse_index 指向宏名后的第一个非空白 token
+    fn expand_macro_call(
+        &mut self,
+        name: &str,
+        macro_obj: &Macro,
+        call_start: usize,
+    ) -> Result<()> {
+        if self.finished() || self.current_kind() != &Token::LParen {
+            let name_end = call_start + name.len();
+            return Err(self
+                .error_at(
+                    Code::MacroArity,
+                    call_start..name_end,
+                    format!(
+                        "macro {} expects {} arguments, but got 0",
+                        name,
+                        macro_obj.params.len()
+                    ),
+                )
+                .with_macro_definition(name, macro_obj)
+                .with_help(format!(
+                    "call it as {}({})",
+                    name,
+                    macro_obj.params.join(", ")
+                )));
+        }
+        self.skip(); // 跳过 LParen
+
+        // 解析参数，只在最外层的逗号处分割
+        let mut args: Vec<String> = Vec::new();
+        let mut current_arg = String::new();
+        let mut depth = 0usize;
+        let mut closed = false;
+        while !self.finished() {
+            let tk = &self.tokens[self.parse_index];
+            match tk.kind {
+                Token::RParen if depth == 0 => {
                     if !current_arg.trim().is_empty() {
                         args.push(current_arg.trim().to_string());
                     }
                     self.skip(); // 跳过 RParen
+                    closed = true;
                     break;
-                } else if tk.kind == Token::Comma && paren_level == 0 {
+                }
+                Token::Comma if depth == 0 => {
                     args.push(current_arg.trim().to_string());
                     current_arg.clear();
                     self.skip(); // 跳过 Comma
-                } else {
-                    if tk.kind == Token::LParen {
-                        paren_level += 1;
-                    } else if tk.kind == Token::RParen {
-                        paren_level -= 1;
+                }
+                _ => {
+                    match tk.kind {
+                        Token::LParen | Token::LBracket | Token::LBrace => depth += 1,
+                        Token::RParen | Token::RBracket | Token::RBrace => {
+                            depth = depth.saturating_sub(1)
+                        }
+                        _ => {}
                     }
                     current_arg.push_str(&tk.text);
                     self.skip();
                 }
             }
-            // 参数数量校验
-            if args.len() != macro_obj.params.len() {
-                log_error!(
-                    "{}: macro {} expects {} arguments, but got {}",
-                    self.current_pos(),
-                    name,
-                    macro_obj.params.len(),
-                    args.len()
-                );
+        }
+        if !closed {
+            return Err(self
+                .error_at(
+                    Code::UnclosedMacroCall,
+                    call_start..call_start + name.len(),
+                    format!("expected ')' to close the arguments of macro {}", name),
+                )
+                .with_note("the arguments run to the end of the file"));
+        }
+        // 参数数量校验
+        if args.len() != macro_obj.params.len() {
+            let call_end = self.tokens[self.parse_index - 1].span.end;
+            return Err(self
+                .error_at(
+                    Code::MacroArity,
+                    call_start..call_end,
+                    format!(
+                        "macro {} expects {} arguments, but got {}",
+                        name,
+                        macro_obj.params.len(),
+                        args.len()
+                    ),
+                )
+                .with_macro_definition(name, macro_obj));
+        }
+        // 展开宏
+        let expanded = self.collapse_lines(&macro_obj.expand(&args));
+        let output_start = self.output.len();
+        self.output.push_str(&expanded);
+        let call_end = self.tokens[self.parse_index - 1].span.end;
+        self.record_origin(output_start, call_start..call_end, Some(name));
+        Ok(())
+    }
+
+    /// 输出空白与普通注释
+    fn consume_trivia(&mut self) {
+        while !self.finished() && matches!(self.current_kind(), Token::Whitespace | Token::Comment)
+        {
+            self.consume();
+        }
+    }
+
+    /// 处理以逗号分隔的表达式列表
+    fn parse_expression_list(&mut self) -> Result<()> {
+        loop {
+            self.parse_expression()?;
+            if self.finished() || self.current_kind() != &Token::Comma {
+                break;
             }
-            // 展开宏
-            let expanded = macro_obj.expand(&args);
-            self.output.push_str(&expanded);
-            self.skip(); // 跳过 LParen
-        } else {
-            // 不是宏调用
-            self.parse_index = current_parse_index; // 回到 ident 位置
-            self.consume(); // ident
-            self.consume_whitespace();
+            self.consume(); // Comma
         }
+        Ok(())
     }
 
-    pub fn expand(&mut self, level: u8, level_map: &HashMap<String, u8>) {
-        self.reset_parse_index();
-        while !self.finished() {
-            match self.current_kind() {
-                // 如果是局部函数，会优先被 LocalKw 捕获，因此这里的 FunctionKw 一定是全局函数
-                Token::FunctionKw => {
-                    self.parse_function_args(true);
-                }
-                Token::ForKw => {
-                    self.parse_for_variables();
+    /// 处理一个表达式，其中的宏照常展开。结束时停在表达式之后的第一个非空白 token 上。
+    /// 只需要找到表达式的边界，因此按 "操作数 (二元运算符 操作数)*" 的形状粗略识别，
+    /// 操作数后可以跟调用参数、下标、字段访问等后缀。
+    fn parse_expression(&mut self) -> Result<()> {
+        let mut expect_operand = true;
+        loop {
+            self.consume_trivia();
+            if self.finished() {
+                return Ok(());
+            }
+            if expect_operand {
+                match self.current_kind() {
+                    Token::NotKw => self.consume(),
+                    Token::Operator if is_unary_operator(self.current_text()) => self.consume(),
+                    Token::Operator if self.current_text() == "..." => {
+                        self.consume();
+                        expect_operand = false;
+                    }
+                    Token::Ident | Token::String | Token::Number | Token::InterpString => {
+                        self.step()?;
+                        expect_operand = false;
+                    }
+                    Token::IfKw if self.dialect == Dialect::Luau => {
+                        self.parse_if_expression()?;
+                        expect_operand = false;
+                    }
+                    Token::LParen | Token::LBrace => {
+                        self.parse_group()?;
+                        expect_operand = false;
+                    }
+                    Token::FunctionKw => {
+                        // 匿名函数：处理到与之匹配的 end 为止
+                        let depth = self.scope_stack.len();
+                        self.step()?;
+                        while !self.finished() && self.scope_stack.len() > depth {
+                            self.step()?;
+                        }
+                        expect_operand = false;
+                    }
+                    _ => return Ok(()),
                 }
-                Token::DoKw | Token::ThenKw | Token::RepeatKw => {
-                    // 进入新作用域
-                    self.enter_scope();
-                    self.consume();
+            } else {
+                match self.current_kind() {
+                    // 调用、下标、以表或字符串为参数的调用
+                    Token::LParen | Token::LBracket | Token::LBrace => self.parse_group()?,
+                    Token::String => self.consume(),
+                    // 字段访问与方法调用，后面的名字不是宏
+                    Token::Operator if matches!(self.current_text(), "." | ":") => {
+                        self.consume();
+                        self.consume_trivia();
+                        if !self.finished() && self.current_kind() == &Token::Ident {
+                            self.consume();
+                        }
+                    }
+                    // Luau 的类型断言 expr :: type
+                    Token::Operator
+                        if self.current_text() == "::" && self.dialect == Dialect::Luau =>
+                    {
+                        self.consume();
+                        self.consume_type();
+                    }
+                    Token::Eq | Token::AndKw | Token::OrKw => {
+                        self.consume();
+                        expect_operand = true;
+                    }
+                    Token::Operator if is_binary_operator(self.current_text()) => {
+                        self.consume();
+                        expect_operand = true;
+                    }
+                    _ => return Ok(()),
                 }
-                Token::EndKw | Token::UntilKw => {
-                    // 退出作用域
-                    self.exit_scope();
+            }
+        }
+    }
+
+    /// 处理一对括号 ()、[] 或 {} 及其内容，结束时停在闭括号之后
+    fn parse_group(&mut self) -> Result<()> {
+        let mut depth = 0usize;
+        while !self.finished() {
+            match self.current_kind() {
+                Token::LParen | Token::LBracket | Token::LBrace => {
+                    depth += 1;
                     self.consume();
                 }
-                Token::ElseKw | Token::ElseIfKw => {
-                    // 退出并进入新的作用域
-                    self.exit_scope();
-                    self.enter_scope();
-                    self.consume();
-                }
-                // 还是要检查宏，如果是局部宏，则允许它在当前作用域生效。
-                Token::MacroComment => {
-                    self.parse_local_macro();
-                }
-                Token::AliasComment => {
-                    self.parse_alias();
-                }
-                Token::LocalKw => {
-                    self.parse_local();
-                }
-                Token::Ident => {
-                    self.parse_ident();
-                }
-                _ => {
+                Token::RParen | Token::RBracket | Token::RBrace => {
                     self.consume();
+                    depth = depth.saturating_sub(1);
+                    if depth == 0 {
+                        return Ok(());
+                    }
                 }
+                _ => self.step()?,
+            }
+        }
+        Ok(())
+    }
+
+    /// 检查展开结果的语法，出错时返回定位到源码（以及所在宏展开）的错误
+    fn validate_output(&self) -> Result<()> {
+        let Err(err) = syntax::check(&self.output, self.dialect) else {
+            return Ok(());
+        };
+        let (line, col) = output_line_col(&self.output, err.offset);
+        let message = format!(
+            "syntax error in generated output (output line {}, column {}): {}",
+            line, col, err.message
+        );
+        let mut error = Error::new(Code::InvalidOutput, message);
+        match self.source_for_output(err.offset) {
+            // 宏展开的结果标出整个调用
+            Some((_, Some(name))) => {
+                let span = self.origin_for_output(err.offset).unwrap().source.clone();
+                error = error.at(self.location(span)).in_macro(name);
+            }
+            Some((pos, None)) => error = error.at(self.location(pos..pos)),
+            None => error = error.at(self.location(0..0)),
+        }
+        Err(error)
+    }
+
+    pub fn expand(&mut self) -> Result<()> {
+        self.reset_parse_index();
+        while !self.finished() {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    /// 处理当前位置的一个语法单元，至少前进一个 token
+    fn step(&mut self) -> Result<()> {
+        match self.current_kind() {
+            // 如果是局部函数，会优先被 LocalKw 捕获，因此这里的 FunctionKw 一定是全局函数
+            Token::FunctionKw => {
+                self.parse_function_args(true)?;
+            }
+            Token::ForKw => {
+                self.parse_for_variables()?;
+            }
+            // 不在语句开头的 if 是 Luau 的 if 表达式，没有 end，也不引入作用域
+            Token::IfKw if self.dialect == Dialect::Luau && !self.at_statement_start() => {
+                self.parse_if_expression()?;
+            }
+            Token::DoKw | Token::ThenKw | Token::RepeatKw => {
+                // 进入新作用域
+                self.enter_scope();
+                self.consume();
+            }
+            Token::EndKw => {
+                // 退出作用域
+                self.exit_scope();
+                self.consume();
+            }
+            Token::UntilKw => {
+                // repeat 循环体中的局部变量在 until 条件中仍然可见，条件结束后才退出作用域
+                self.consume();
+                self.parse_expression()?;
+                self.exit_scope();
+            }
+            Token::ElseKw => {
+                // 退出并进入新的作用域
+                self.exit_scope();
+                self.enter_scope();
+                self.consume();
+            }
+            Token::ElseIfKw => {
+                // 退出上一个分支的作用域，条件在外层求值，新的作用域由随后的 then 打开
+                self.exit_scope();
+                self.consume();
+            }
+            // 还是要检查宏，如果是局部宏，则允许它在当前作用域生效。
+            // 宏定义不会输出，保持行号模式下替换为同样数量的空行
+            Token::MacroComment => {
+                let start = self.parse_index;
+                self.parse_local_macro()?;
+                self.pad_skipped_lines(start);
+            }
+            Token::AliasComment => {
+                let start = self.parse_index;
+                self.parse_alias()?;
+                self.pad_skipped_lines(start);
+            }
+            Token::Require if self.dropped_require_end().is_some() => {
+                let (end, suffixed) = self.dropped_require_end().unwrap();
+                self.drop_require(end, suffixed);
+            }
+            // `-- @use` 不输出，列出的宏从这里开始生效
+            Token::UseComment => {
+                let start = self.parse_index;
+                self.apply_imports(self.tokens[start].span.end);
+                self.skip();
+                self.pad_skipped_lines(start);
+            }
+            Token::LocalKw => {
+                self.parse_local()?;
+            }
+            Token::Ident if self.at_type_statement() => {
+                self.parse_type_statement();
+            }
+            Token::Ident => {
+                self.parse_ident()?;
+            }
+            Token::InterpString => {
+                self.expand_interpolated_string()?;
+            }
+            _ => {
+                self.consume();
             }
         }
+        Ok(())
     }
 }
 
-// 解析 "-- @if name ..." 的 name
-fn parse_if_name(comment_text: &str) -> &str {
-    // 去掉开头 "--" 后的部分
-    let s = comment_text.trim_start();
-    let s = s.strip_prefix("--").unwrap_or(s).trim_start();
-    // 去掉 "@if"
-    let s = s.strip_prefix("@if").unwrap_or(s).trim_start();
-    // 取到第一个空白或行尾
-    let bytes = s.as_bytes();
-    let mut end = bytes.len();
-    for (idx, b) in bytes.iter().enumerate() {
-        if b.is_ascii_whitespace() {
-            end = idx;
-            break;
-        }
-    }
-    &s[..end]
+/// 设置作用域后展开一个文件，需要时检查生成代码的语法
+fn expand_file(
+    file: &mut File,
+    imports: &[Import],
+    global_macro_map: &HashMap<PathBuf, HashMap<String, Macro>>,
+    options: &OutputOptions,
+) -> Result<()> {
+    file.set_stacks(imports, global_macro_map);
+    file.preserve_lines = options.preserve_lines;
+    file.expand()?;
+    if options.validate {
+        file.validate_output()?;
+    }
+    Ok(())
+}
+
+/// 一组宏的指纹，由宏名、参数与模板决定，不含定义的位置
+pub fn macro_fingerprint(macros: &HashMap<String, Macro>) -> u64 {
+    let mut names: Vec<&String> = macros.keys().collect();
+    names.sort();
+    let mut key = String::new();
+    for name in names {
+        let macro_obj = &macros[name];
+        key.push_str(&format!(
+            "{}({})={}\0",
+            name,
+            macro_obj.params.join(","),
+            macro_obj.template
+        ));
+    }
+    fnv1a(key.as_bytes())
+}
+
+/// 输出是否只有空白与注释
+fn is_blank_output(output: &str, dialect: Dialect) -> bool {
+    tokenize(output, dialect).iter().all(|t| {
+        matches!(
+            t.kind,
+            Token::Whitespace
+                | Token::Comment
+                | Token::MacroComment
+                | Token::AliasComment
+                | Token::UseComment
+                | Token::IfComment
+                | Token::EndIfComment
+        )
+    })
+}
+
+/// 模块的输出是否为空：本次处理的文件按输出判断，其余文件沿用缓存中的结果
+fn module_is_empty(project: &Project, path: &Path) -> bool {
+    match project
+        .files
+        .get(path)
+        .filter(|_| !project.unchanged.contains(path))
+    {
+        Some(file) => is_blank_output(&file.output, file.dialect),
+        None => project
+            .cache
+            .files
+            .get(path)
+            .is_some_and(|entry| entry.empty),
+    }
+}
+
+/// 写入输出文件，以及需要时的源码映射
+fn write_output(out_path: &Path, file: &File, options: &OutputOptions) -> Result<()> {
+    let io_error = |path: &Path, err: std::io::Error| {
+        Error::new(
+            Code::Io,
+            format!("failed to write {}: {}", path.display(), err),
+        )
+    };
+    if let Some(parent) = out_path.parent() {
+        std::fs::create_dir_all(parent).map_err(|err| io_error(parent, err))?;
+    }
+    std::fs::write(out_path, &file.output).map_err(|err| io_error(out_path, err))?;
+    if options.source_map {
+        let source_map = SourceMap::from_file(file);
+        let data = serde_json::to_string(&source_map).unwrap();
+        let map_path = SourceMap::path_for(out_path);
+        std::fs::write(&map_path, data).map_err(|err| io_error(&map_path, err))?;
+    }
+    Ok(())
+}
+
+/// 文本中字节偏移 offset 所在的行号与列号（从 1 开始）
+fn output_line_col(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let col = offset - before.rfind('\n').map_or(0, |idx| idx + 1) + 1;
+    (line, col)
+}
+
+/// 索引链 a.b:c 的链首名字
+fn root_name(name: &str) -> &str {
+    match name.find(['.', ':']) {
+        Some(idx) => &name[..idx],
+        None => name,
+    }
+}
+
+fn is_unary_operator(op: &str) -> bool {
+    matches!(op, "-" | "#" | "~")
+}
+
+fn is_binary_operator(op: &str) -> bool {
+    matches!(
+        op,
+        "+" | "-"
+            | "*"
+            | "/"
+            | "//"
+            | "%"
+            | "^"
+            | ".."
+            | "~="
+            | "<"
+            | "<="
+            | ">"
+            | ">="
+            | "&"
+            | "|"
+            | "~"
+            | "<<"
+            | ">>"
+    )
 }
@@ -1,82 +1,248 @@
-mod log;
-mod macros;
-mod project;
-mod token;
-use std::{collections::HashMap, path::PathBuf};
-
-use crate::macros::Processor;
-use project::Project;
+// 与库一致，读取配置的错误直接返回 Error
+#![allow(clippy::result_large_err)]
+
+use std::path::{Path, PathBuf};
+
+use dlua::graph::{self, GraphFormat};
+use dlua::log::{self, ColorChoice, Verbosity};
+use dlua::{
+    Code, Config, Diagnostic, Error, OutputOptions, Severity, log_error, log_info, resolve, sarif,
+    trace,
+};
 use serde_json::Value;
 
 fn main() {
+    // 输出选项对子命令同样生效
+    let color = match flag_value("--color").as_deref() {
+        None => ColorChoice::Auto,
+        Some(name) => ColorChoice::from_name(name).unwrap_or_else(|| {
+            log_error!(
+                "Unknown color choice '{}', expected one of auto, always, never",
+                name
+            );
+            std::process::exit(1);
+        }),
+    };
+    log::set_color(color);
+    if std::env::args().any(|arg| arg == "-q" || arg == "--quiet") {
+        log::set_verbosity(Verbosity::Quiet);
+    } else if std::env::args().any(|arg| arg == "-v" || arg == "--verbose") {
+        log::set_verbosity(Verbosity::Verbose);
+    }
+
+    // 选项可以写在任意位置，剩下的是子命令与位置参数
+    let args = positionals();
+
+    // 子命令：dlua trace <export_path>
+    if args.first().map(String::as_str) == Some("trace") {
+        let Some(export_path) = args.get(1) else {
+            usage_error("Missing export path", "dlua trace <export_path>");
+        };
+        match trace::run(&PathBuf::from(export_path)) {
+            Ok(rewritten) => print!("{}", rewritten),
+            Err(err) => fail(err),
+        }
+        return;
+    }
+
+    // 子命令：dlua resolve <module> [root]，按 dlua.json 中的 require_paths 查找模块
+    if args.first().map(String::as_str) == Some("resolve") {
+        let Some(module) = args.get(1) else {
+            usage_error("Missing module name", "dlua resolve <module> [root]");
+        };
+        let root = args.get(2).map_or(".", String::as_str);
+        let mut config = Config::new(root, PathBuf::new());
+        if let Err(err) = apply_config_json(&mut config) {
+            fail(err);
+        }
+        match resolve::run(&config, module) {
+            Ok(listing) => print!("{}", listing),
+            Err(err) => fail(err),
+        }
+        return;
+    }
+
+    // 子命令：dlua graph [root] [--format dot|json|mermaid] [--from module]，输出 require 关系图
+    if args.first().map(String::as_str) == Some("graph") {
+        let format = match flag_value("--format").as_deref() {
+            None => GraphFormat::Dot,
+            Some(name) => GraphFormat::from_name(name).unwrap_or_else(|| {
+                log_error!(
+                    "Unknown graph format '{}', expected one of dot, json, mermaid",
+                    name
+                );
+                std::process::exit(1);
+            }),
+        };
+        let root = args.get(1).map_or(".", String::as_str);
+        let mut config = Config::new(root, PathBuf::new());
+        if let Err(err) = apply_config_json(&mut config) {
+            fail(err);
+        }
+        match graph::run(&config, format, flag_value("--from").as_deref()) {
+            Ok(graph) => print!("{}", graph),
+            Err(err) => fail(err),
+        }
+        return;
+    }
+
     // 入口文件路径（从命令行参数获取）
-    let entry = std::env::args()
-        .nth(1)
-        .expect("请提供入口文件路径作为第一个参数");
-    let export_path = std::env::args()
-        .nth(2)
-        .expect("请提供导出路径作为第二个参数（必须为文件夹）");
+    let (Some(entry), Some(export_path)) = (args.first(), args.get(1)) else {
+        usage_error(
+            "Missing entry or export path",
+            "dlua <entry> <export_path> [options]",
+        );
+    };
     let export_path = PathBuf::from(export_path);
 
-    let full = std::env::args().any(|arg| arg == "--full");
-
-    // 尝试解析当前目录的 dlua.json
-    let _config_path = std::env::current_dir()
-        .expect("获取当前目录失败")
-        .join("dlua.json");
+    let mut config = Config::new(entry, export_path.clone());
+    config.full = std::env::args().any(|arg| arg == "--full");
+    config.output = OutputOptions {
+        // 写入前检查生成的代码是否是合法的 Lua
+        validate: std::env::args().any(|arg| arg == "--validate"),
+        // 为每个输出文件生成源码映射
+        source_map: std::env::args().any(|arg| arg == "--source-map"),
+        // 输出与源码逐行对应，运行时的行号可以直接用于源码
+        preserve_lines: std::env::args().any(|arg| arg == "--preserve-lines"),
+        // 不写入只用来导入宏、输出为空的模块，并去掉对它们的 require
+        strip_empty_modules: std::env::args().any(|arg| arg == "--strip-empty-modules"),
+    };
 
-    // 加载配置文件内容（如果存在）
-    let _config: Option<Value> = if _config_path.exists() {
-        let config_content = std::fs::read_to_string(&_config_path).expect("读取配置文件失败");
-        let json: Value = serde_json::from_str(&config_content).expect("解析配置文件失败");
-        Some(json)
-    } else {
-        None
+    // 诊断信息的输出格式：human（默认）或每行一个 JSON 对象
+    let json_output = match flag_value("--message-format").as_deref() {
+        None | Some("human") => false,
+        Some("json") => true,
+        Some(other) => {
+            log_error!(
+                "Unknown message format '{}', expected one of human, json",
+                other
+            );
+            std::process::exit(1);
+        }
     };
+    // 额外把诊断写入 SARIF 文件
+    let sarif_path = flag_value("--sarif").map(PathBuf::from);
 
-    let require_paths: Option<Vec<String>> = if let Some(config) = &_config {
-        if let Some(paths) = config.get("require_paths") {
-            if let Some(arr) = paths.as_array() {
-                Some(
-                    arr.iter()
-                        .filter_map(|v| v.as_str().map(|s| s.to_string()))
-                        .collect(),
-                )
-            } else {
-                None
+    if let Err(err) = apply_config_json(&mut config) {
+        report(&[err.into()], json_output, sarif_path.as_deref());
+        std::process::exit(1);
+    }
+
+    match dlua::build(&config) {
+        Ok(warnings) => {
+            report(&warnings, json_output, sarif_path.as_deref());
+            if !json_output {
+                log_info!("Project compiled to：{:?}", export_path);
             }
+        }
+        Err(diagnostics) => {
+            report(&diagnostics, json_output, sarif_path.as_deref());
+            std::process::exit(1);
+        }
+    }
+}
+
+/// 子命令出错时输出错误并以非零状态退出
+fn fail(err: Error) -> ! {
+    eprintln!("{}\n", Diagnostic::from(err).render());
+    std::process::exit(1);
+}
+
+/// 缺少位置参数时给出用法并以非零状态退出
+fn usage_error(message: &str, usage: &str) -> ! {
+    log_error!("{}\nUsage: {}", message, usage);
+    std::process::exit(1);
+}
+
+/// 读取当前目录的 dlua.json（如果存在）并应用到 config。读取或解析失败时返回 DL0302
+fn apply_config_json(config: &mut Config) -> dlua::Result<()> {
+    let config_path = Path::new("dlua.json");
+    if !config_path.exists() {
+        return Ok(());
+    }
+    let content = std::fs::read_to_string(config_path)
+        .map_err(|err| Error::new(Code::Config, format!("failed to read dlua.json: {}", err)))?;
+    let json: Value = serde_json::from_str(&content)
+        .map_err(|err| Error::new(Code::Config, format!("failed to parse dlua.json: {}", err)))?;
+    config.apply_json(&json)
+}
+
+/// 带值的选项，`--name value` 中的 value 不是位置参数
+const VALUE_FLAGS: &[&str] = &[
+    "--color",
+    "--message-format",
+    "--sarif",
+    "--format",
+    "--from",
+];
+/// 不带值的选项
+const SWITCHES: &[&str] = &[
+    "-q",
+    "--quiet",
+    "-v",
+    "--verbose",
+    "--full",
+    "--validate",
+    "--source-map",
+    "--preserve-lines",
+    "--strip-empty-modules",
+];
+
+/// 去掉选项（以及选项的值）之后的命令行参数。遇到不认识的选项时报错退出，
+/// 免得它被当作路径，或让后面的位置参数错位
+fn positionals() -> Vec<String> {
+    let mut positionals = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if VALUE_FLAGS.contains(&arg.as_str()) {
+            args.next();
+        } else if SWITCHES.contains(&arg.as_str()) {
+            continue;
+        } else if arg.starts_with('-') && arg != "-" {
+            log_error!("Unknown option '{}'", arg);
+            std::process::exit(1);
         } else {
-            None
+            positionals.push(arg);
         }
-    } else {
-        None
-    };
+    }
+    positionals
+}
 
-    // 记录当前时间
-    // let _start_time = std::time::Instant::now();
-    let proj = Project::load(&entry, require_paths, &export_path, full).expect("加载项目失败");
-    // let _duration = _start_time.elapsed();
-    // println!("项目加载完成，耗时: {:?}", _duration);
-
-    // 再记录时间
-    // let _start_time = std::time::Instant::now();
-    let mut processor = Processor::new(proj);
-    // let _duration = _start_time.elapsed();
-    // println!("全局宏收集完成，耗时: {:?}", _duration);
-
-    let level_map: HashMap<String, u8> = HashMap::from([
-        ("debug".to_string(), 0),
-        ("info".to_string(), 1),
-        ("release".to_string(), 2),
-    ]);
-
-    let user_level = 1;
-
-    // let _start_time = std::time::Instant::now();
-    processor.expand_all_with_levels(user_level, &level_map, &export_path);
-    // let _duration = _start_time.elapsed();
-    // println!("宏展开完成，耗时: {:?}", _duration);
-    // println!("{:?}", file_global_macros.macro_map);
-
-    log_info!("Project compiled to：{:?}", export_path);
+/// 形如 `--name value` 的选项的值
+fn flag_value(name: &str) -> Option<String> {
+    let args: Vec<String> = std::env::args().collect();
+    let index = args.iter().position(|arg| arg == name)?;
+    match args.get(index + 1) {
+        Some(value) => Some(value.clone()),
+        None => {
+            log_error!("Missing value for option {}", name);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// 输出所有诊断信息，最后给出错误与警告的数量；指定了 sarif_path 时同时写入 SARIF 文件
+fn report(diagnostics: &[Diagnostic], json_output: bool, sarif_path: Option<&Path>) {
+    let mut errors = 0;
+    let mut warnings = 0;
+    for diagnostic in diagnostics {
+        match diagnostic.severity {
+            Severity::Error => errors += 1,
+            Severity::Warning => warnings += 1,
+        }
+        // JSON 是给其它程序读取的结果，写到标准输出；其余信息都写到标准错误
+        if json_output {
+            println!("{}", diagnostic.to_json());
+        } else {
+            eprintln!("{}\n", diagnostic.render());
+        }
+    }
+    if let Some(path) = sarif_path
+        && let Err(err) = sarif::write(path, diagnostics)
+    {
+        log_error!("{}", err);
+    }
+    if errors > 0 && !json_output {
+        log_error!("Build failed: {} error(s), {} warning(s)", errors, warnings);
+    }
 }
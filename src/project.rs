@@ -1,36 +1,83 @@
-use crate::{log_info, log_warn, macros::Macro, token::Token};
-use logos::Logos;
+use crate::{
+    Config,
+    code::Code,
+    diagnostics::{Diagnostic, Diagnostics},
+    error::{Error, Location},
+    log_verbose,
+    macros::{Import, Macro, macro_fingerprint},
+    resolve::{DynamicRequires, SearchPath},
+    token::{Dialect, Token, TokenWithText, tokenize},
+};
 use rayon::prelude::*;
+use regex::Regex;
 use std::{
     collections::{HashMap, HashSet},
     fs,
-    path::PathBuf,
+    ops::Range,
+    path::{Path, PathBuf},
     time::SystemTime,
 };
 
 use serde::{Deserialize, Serialize};
-use serde_json;
 use walkdir::WalkDir;
 
-/// 每个文件的缓存信息：mtime（秒）和依赖列表
+/// 缓存格式的版本，格式改变时递增，旧版本的缓存整个丢弃
+pub const CACHE_VERSION: u32 = 3;
+
+/// 每个文件的缓存信息：修改时间、大小与内容哈希，依赖列表，以及上次的输出是否为空
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FileCache {
+    // 修改时间（纳秒）与大小都没变时认为文件没有改变，不再读取内容
     pub mtime: u64,
+    pub size: u64,
+    // 内容的 FNV-1a 哈希，修改时间或大小改变而内容没变时（如 git checkout）不重新处理
+    pub hash: u64,
+    // 导出给 require 它的文件的宏的指纹，改变时这些文件才需要重新展开
+    pub macros: u64,
     pub deps: Vec<PathBuf>,
+    // deps 中运行时会 require 的模块，不含只通过 `-- @use` 导入宏的模块
+    #[serde(default)]
+    pub requires: Vec<PathBuf>,
+    #[serde(default)]
+    pub empty: bool,
 }
 
-/// 全量构建缓存（序列化到磁盘）
+/// 全量构建缓存（序列化到磁盘）。格式版本、dlua 版本或配置改变时整个缓存失效
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BuildCache {
+    pub version: u32,
+    pub dlua_version: String,
+    // 影响输出的配置的哈希，见 Config::fingerprint
+    pub config_hash: u64,
     pub files: HashMap<PathBuf, FileCache>,
 }
 
+impl BuildCache {
+    pub fn new(config_hash: u64) -> Self {
+        BuildCache {
+            version: CACHE_VERSION,
+            dlua_version: env!("CARGO_PKG_VERSION").to_string(),
+            config_hash,
+            files: HashMap::new(),
+        }
+    }
+}
+
+/// 与缓存相比文件的变化
+enum Change {
+    // 内容改变或没有缓存
+    Modified,
+    // 修改时间或大小改变但内容相同，只需要更新缓存中的修改时间与大小
+    Touched { mtime: u64, size: u64 },
+}
+
+/// 输出中的一段文本来自源码的哪里：output_start 起的文本对应源码 source 区间。
+/// 宏展开的结果整体对应调用处，并记录宏名
 #[derive(Debug, Clone)]
-pub struct TokenWithText {
-    pub kind: Token,
-    pub text: String,
-    // 字节区间，用于定位
-    pub span: std::ops::Range<usize>,
+pub struct Origin {
+    pub output_start: usize,
+    pub source: Range<usize>,
+    pub macro_name: Option<String>,
 }
 
 #[derive(Debug)]
@@ -38,25 +85,56 @@ pub struct File {
     pub path: PathBuf,
     pub tokens: Vec<TokenWithText>,
     pub scope_stack: Vec<HashMap<String, Macro>>,
-    pub shadow_stack: Vec<HashSet<String>>,
+    // 遮蔽宏的名字及其声明位置
+    pub shadow_stack: Vec<HashMap<String, Range<usize>>>,
+    // 尚未生效的 require 导入，按位置倒序排列
+    pub pending_imports: Vec<Import>,
+    // 指向空模块、需要去掉的 require，记录模块名的位置
+    pub dropped_requires: HashSet<usize>,
+    // 尚未闭合的括号及其所在的作用域深度，用于识别表构造器中的键
+    pub bracket_stack: Vec<(Token, usize)>,
     pub line_starts: Vec<usize>,
+    // 源码原文，用于渲染诊断
+    pub source: String,
     pub output: String,
+    // 输出到源码的位置映射，按 output_start 递增
+    pub origins: Vec<Origin>,
     pub parse_index: usize,
+    pub dialect: Dialect,
+    // 保持输出与源码逐行对应
+    pub preserve_lines: bool,
 }
 
 impl File {
-    pub fn new(path: &PathBuf, tokens: Vec<TokenWithText>) -> Self {
+    pub fn new(path: &Path, tokens: Vec<TokenWithText>) -> Self {
         Self {
-            path: path.clone(),
-            tokens: tokens,
+            path: path.to_path_buf(),
+            tokens,
             scope_stack: Vec::new(),
             shadow_stack: Vec::new(),
+            pending_imports: Vec::new(),
+            dropped_requires: HashSet::new(),
+            bracket_stack: Vec::new(),
             line_starts: Vec::new(),
+            source: String::new(),
             output: String::new(),
+            origins: Vec::new(),
             parse_index: 0,
+            dialect: Dialect::default(),
+            preserve_lines: false,
         }
     }
 
+    /// 读取并分词一个源码文件
+    pub fn read(path: &Path, dialect: Dialect) -> std::io::Result<Self> {
+        let code = fs::read_to_string(path)?;
+        let mut file = File::new(path, tokenize(&code, dialect));
+        file.line_starts = compute_line_starts(&code);
+        file.source = code;
+        file.dialect = dialect;
+        Ok(file)
+    }
+
     pub fn line_col_from_pos(&self, pos: usize) -> (usize, usize) {
         match self.line_starts.binary_search(&pos) {
             Ok(line_idx) => (line_idx + 1, 1),
@@ -70,28 +148,160 @@ impl File {
     }
 
     fn pos_for_token(&self, token: &TokenWithText) -> String {
-        let (line, col) = self.line_col_from_pos(token.span.start);
-        format!("{}:{}:{}", self.path.canonicalize().unwrap_or(self.path.clone()).display(), line, col)
+        self.pos_for_source(token.span.start)
     }
 
-    pub fn pos_for_index(&self, index: usize)->String{
-        if self.tokens.is_empty(){
-            return format!("{}:1:1",self.path.canonicalize().unwrap_or(self.path.clone()).display());
+    /// 源码中字节偏移 pos 的位置描述 path:line:col
+    pub fn pos_for_source(&self, pos: usize) -> String {
+        let (line, col) = self.line_col_from_pos(pos);
+        format!(
+            "{}:{}:{}",
+            self.path
+                .canonicalize()
+                .unwrap_or(self.path.clone())
+                .display(),
+            line,
+            col
+        )
+    }
+
+    /// 输出中字节偏移 offset 对应的源码偏移，以及它所在的宏展开的宏名。
+    /// 原样输出的 token 精确到字节，宏展开的结果对应调用处的开头
+    pub fn source_for_output(&self, offset: usize) -> Option<(usize, Option<&str>)> {
+        let origin = self.origin_for_output(offset)?;
+        match &origin.macro_name {
+            Some(name) => Some((origin.source.start, Some(name.as_str()))),
+            None => {
+                let delta = (offset - origin.output_start).min(origin.source.len());
+                Some((origin.source.start + delta, None))
+            }
+        }
+    }
+
+    /// 输出中字节偏移 offset 所在的一段输出的来源
+    pub fn origin_for_output(&self, offset: usize) -> Option<&Origin> {
+        let idx = self
+            .origins
+            .partition_point(|o| o.output_start <= offset)
+            .checked_sub(1)?;
+        Some(&self.origins[idx])
+    }
+
+    pub fn pos_for_index(&self, index: usize) -> String {
+        if self.tokens.is_empty() {
+            return format!(
+                "{}:1:1",
+                self.path
+                    .canonicalize()
+                    .unwrap_or(self.path.clone())
+                    .display()
+            );
         }
-        if index < self.tokens.len(){
+        if index < self.tokens.len() {
             let token = &self.tokens[index];
             self.pos_for_token(token)
-        }else{
-            let last_token = &self.tokens[self.tokens.len()-1];
+        } else {
+            let last_token = &self.tokens[self.tokens.len() - 1];
             self.pos_for_token(last_token)
         }
     }
 
+    /// 源码中 span 区间的位置
+    pub fn location(&self, span: Range<usize>) -> Location {
+        let (line, column) = self.line_col_from_pos(span.start);
+        let (end_line, end_column) = self.line_col_from_pos(span.end);
+        let line_start = self.line_starts.get(line - 1).copied().unwrap_or(0);
+        let line_end = self
+            .line_starts
+            .get(line)
+            .copied()
+            .unwrap_or(self.source.len());
+        let source_line = self
+            .source
+            .get(line_start..line_end)
+            .unwrap_or("")
+            .trim_end_matches(['\n', '\r'])
+            .to_string();
+        Location {
+            path: self.path.canonicalize().unwrap_or(self.path.clone()),
+            line,
+            column,
+            end_line,
+            end_column,
+            span,
+            source_line,
+        }
+    }
+
+    /// 位于当前 token 的宏错误
+    pub fn error(&self, code: Code, message: impl Into<String>) -> Error {
+        let span = match self.tokens.get(self.parse_index).or(self.tokens.last()) {
+            Some(token) => token.span.clone(),
+            None => 0..0,
+        };
+        Error::new(code, message).at(self.location(span))
+    }
+
+    /// 位于源码 span 区间的宏错误
+    pub fn error_at(&self, code: Code, span: Range<usize>, message: impl Into<String>) -> Error {
+        Error::new(code, message).at(self.location(span))
+    }
+
+    /// 源码中 `-- @allow(DL0001, ...)` 注释所在的行与忽略的编号，不认识的编号被跳过
+    pub fn allowed_codes(&self) -> Vec<(usize, Code)> {
+        let allow = Regex::new(r"^--\s*@allow\(([^)]*)\)").unwrap();
+        let mut allowed = Vec::new();
+        for token in self.tokens.iter().filter(|t| t.kind == Token::Comment) {
+            let Some(caps) = allow.captures(&token.text) else {
+                continue;
+            };
+            let (line, _) = self.line_col_from_pos(token.span.start);
+            for code in caps[1].split(',').filter_map(|c| Code::parse(c.trim())) {
+                allowed.push((line, code));
+            }
+        }
+        allowed
+    }
+
     pub fn current_pos(&self) -> String {
         self.pos_for_index(self.parse_index)
     }
 }
 
+/// 源码中的一处 require
+#[derive(Debug, Clone)]
+pub struct RequireSite {
+    // 解析得到的模块文件
+    pub path: PathBuf,
+    // 模块名字符串的位置
+    pub span: Range<usize>,
+    // 上一行是 `-- @export`：把这个模块导出的宏继续导出给 require 本文件的模块
+    pub export: bool,
+    // `-- @use` 只导入列出的宏；require 为 None，导入全部
+    pub items: Option<Vec<UseItem>>,
+}
+
+/// `-- @use` 中列出的一个宏
+#[derive(Debug, Clone)]
+pub struct UseItem {
+    pub name: String,
+    // `as` 之后的名字，没有重命名时与 name 相同
+    pub alias: String,
+    pub span: Range<usize>,
+}
+
+/// 从源码中找到的一处模块引用，尚未解析为文件
+#[derive(Debug, Clone)]
+pub struct RequiredModule {
+    // 模块名；动态 require 时是参数表达式的源码
+    pub name: String,
+    pub span: Range<usize>,
+    pub export: bool,
+    pub items: Option<Vec<UseItem>>,
+    // 参数不是字符串常量
+    pub dynamic: bool,
+}
+
 /// Project：根目录 + 缓存 + 所有源码 token 与依赖关系
 #[derive(Debug)]
 pub struct Project {
@@ -99,6 +309,10 @@ pub struct Project {
     pub files: HashMap<PathBuf, File>,
     pub require_relations: HashMap<PathBuf, Vec<PathBuf>>, // 正向：file -> deps
     pub reverse_require: HashMap<PathBuf, Vec<PathBuf>>,   // 反向：file -> dependents
+    // 本次分词的文件中按出现顺序排列的 require
+    pub require_sites: HashMap<PathBuf, Vec<RequireSite>>,
+    // 没有改变、只为了读取导出的宏而加载的文件，不重新展开和输出
+    pub unchanged: HashSet<PathBuf>,
     pub cache: BuildCache,
     pub cache_path: PathBuf,
 }
@@ -106,153 +320,138 @@ pub struct Project {
 impl Project {
     /// 加载目录，自动加载缓存（cache 成为 Project 成员），返回 Project。
     /// 退出时 Drop 会把 cache 持久化到 cache_path。
-    pub fn load(
-        root: impl AsRef<std::path::Path>,
-        require_paths: Option<Vec<String>>,
-        export_path: &PathBuf,
-        full: bool,
-    ) -> std::io::Result<Self> {
-        let root_path = root.as_ref().to_path_buf();
-        let cache_path = export_path.join(".dlua_cache.json");
+    pub fn load(config: &Config, diagnostics: &Diagnostics) -> std::io::Result<Self> {
+        let root_path = config.entry.clone();
+        let cache_path = config.export_path.join(".dlua_cache.json");
 
         // 初始化 Project（cache 先用 load_cache 填充）
         let mut project = Project {
             root: root_path.clone(),
             files: HashMap::new(),
             require_relations: HashMap::new(),
+            require_sites: HashMap::new(),
             reverse_require: HashMap::new(),
-            cache: Self::load_cache(cache_path.to_str().unwrap_or(".dlua_cache.json")),
-            cache_path
+            unchanged: HashSet::new(),
+            cache: Self::load_cache(
+                cache_path.to_str().unwrap_or(".dlua_cache.json"),
+                config.fingerprint(),
+            ),
+            cache_path,
         };
 
         let t0 = std::time::Instant::now();
         // 收集所有 lua 文件路径（整个目录）
-        let all_lua_files: Vec<PathBuf> = WalkDir::new(&project.root)
-            .into_iter()
-            .filter_map(Result::ok)
-            .filter(|e| {
-                e.path()
-                    .extension()
-                    .map(|ext| ext == "lua")
-                    .unwrap_or(false)
-            })
-            .map(|e| e.path().to_path_buf())
-            .collect();
+        let all_lua_files = Self::lua_files(&project.root);
 
-        log_info!(
+        log_verbose!(
             "Collected {} Lua files in {:.2?}",
             all_lua_files.len(),
             t0.elapsed()
         );
         let t1 = std::time::Instant::now();
-
-        // 确定需要处理的文件列表
-        let to_tokenize: Vec<PathBuf> = if full {
-            all_lua_files.clone()
+        let search_path = SearchPath::new(config.require_paths.as_deref().unwrap_or_default());
+        let mut unresolved_requires: HashSet<String> = HashSet::new();
+        if config.full {
+            project.add_files(
+                &all_lua_files,
+                config,
+                &search_path,
+                &mut unresolved_requires,
+                diagnostics,
+            );
         } else {
-            let changed_files = Self::find_changed_files(&all_lua_files, &project.cache);
-            log_info!("Found {} changed files", changed_files.len());
+            let changed_files = Self::find_changed_files(&all_lua_files, &mut project.cache);
+            log_verbose!("Found {} changed files", changed_files.len());
 
-            // 从 cache 恢复依赖关系（尽可能），以便后面计算受影响集合
+            // 从 cache 恢复依赖关系（尽可能），以便后面找到 require 它们的文件
             for (path, file_cache) in &project.cache.files {
-                if project.require_relations.contains_key(path) {
-                    continue;
-                }
-                if !file_cache.deps.is_empty() {
+                for dep in &file_cache.deps {
                     project
                         .require_relations
-                        .insert(path.clone(), file_cache.deps.clone());
-                    for dep in &file_cache.deps {
-                        project
-                            .reverse_require
-                            .entry(dep.clone())
-                            .or_insert_with(Vec::new)
-                            .push(path.clone());
-                    }
+                        .entry(path.clone())
+                        .or_default()
+                        .push(dep.clone());
+                    project
+                        .reverse_require
+                        .entry(dep.clone())
+                        .or_default()
+                        .push(path.clone());
                 }
             }
-            let affected_set = Self::compute_affected_set(
-                &changed_files,
-                &project.require_relations,
-                &project.reverse_require,
-            );
-            affected_set.iter().cloned().collect()
-        };
-
-        log_info!(
-            "Total affected files (changed + transitive deps/owners): {}",
-            to_tokenize.len()
-        );
 
-        // 并行读取与分词（对受影响的所有文件）
-        let files: Vec<(PathBuf, File)> = to_tokenize
-            .par_iter()
-            .filter_map(|path| {
-                let code = fs::read_to_string(path).ok()?;
-                let line_starts = compute_line_starts(&code);
-                let mut lexer = Token::lexer(&code);
-                let mut tokens_with_text = Vec::new();
-                let src = lexer.source();
-                while let Some(token_result) = lexer.next() {
-                    match token_result {
-                        Ok(token) => {
-                            let span = lexer.span();
-                            let text = src[span.start..span.end].to_string();
-                            tokens_with_text.push(TokenWithText {
-                                kind: token,
-                                text,
-                                span: span.start..span.end,
-                            });
-                        }
-                        Err(_) => {
-                            // 忽略单个 token 错误；日志留给主线程
-                        }
-                    }
+            // 导出的宏改变了的文件，require 它们的文件需要重新展开；内容没变的文件导出的宏不会变，
+            // 除非它再导出的模块变了。去掉空模块时，模块是否为空也可能改变，require 它的文件都要重新处理
+            let mut exports_changed: HashSet<PathBuf> = HashSet::new();
+            let mut pending = changed_files;
+            while !pending.is_empty() {
+                let changed = project.add_files(
+                    &pending,
+                    config,
+                    &search_path,
+                    &mut unresolved_requires,
+                    diagnostics,
+                );
+                if config.output.strip_empty_modules {
+                    exports_changed.extend(pending);
+                } else {
+                    exports_changed.extend(changed);
                 }
-                let mut file = File::new(path, tokens_with_text);
-                file.line_starts = line_starts;
-                Some((path.clone(), file))
-            })
-            .collect();
+                project.propagate_reexports(&mut exports_changed);
+                let mut next: Vec<PathBuf> = exports_changed
+                    .iter()
+                    .filter_map(|path| project.reverse_require.get(path))
+                    .flatten()
+                    .filter(|path| !project.files.contains_key(*path))
+                    .cloned()
+                    .collect();
+                next.sort();
+                next.dedup();
+                pending = next;
+            }
+            log_verbose!(
+                "Total affected files (changed + dependents of changed macros): {}",
+                project.files.len()
+            );
 
-        // 合并分词结果到 project.files（覆盖或新增）
-        for (path, file) in files {
-            project.files.insert(path, file);
+            // 重新处理的文件需要它们 require 的模块导出的宏，这些模块只读取宏，不重新输出
+            let mut needed: Vec<PathBuf> = project
+                .files
+                .keys()
+                .filter_map(|path| project.require_relations.get(path))
+                .flatten()
+                .filter(|path| !project.files.contains_key(*path))
+                .cloned()
+                .collect();
+            while !needed.is_empty() {
+                needed.sort();
+                needed.dedup();
+                project.unchanged.extend(needed.iter().cloned());
+                project.add_files(
+                    &needed,
+                    config,
+                    &search_path,
+                    &mut unresolved_requires,
+                    diagnostics,
+                );
+                needed = needed
+                    .iter()
+                    .filter_map(|path| project.require_sites.get(path))
+                    .flatten()
+                    .filter(|site| site.export && !project.files.contains_key(&site.path))
+                    .map(|site| site.path.clone())
+                    .collect();
+            }
+            log_verbose!(
+                "Loaded {} unchanged modules for their macros",
+                project.unchanged.len()
+            );
         }
-
-        log_info!(
-            "Tokenized {} Lua files in {:.2?}",
+        log_verbose!(
+            "Tokenized and resolved {} Lua files in {:.2?}",
             project.files.len(),
             t1.elapsed()
         );
 
-        // 解析依赖关系：对刚分词的文件解析 require，并更新 require_relations / reverse_require / cache
-        let require_paths = require_paths.unwrap_or_else(|| vec![".".to_string()]);
-        let mut unresolved_requires: HashSet<PathBuf> = HashSet::new();
-
-        for (path, file) in &project.files {
-            let mut deps: Vec<PathBuf> = Vec::new();
-            for req in Self::get_required_modules(&file.tokens) {
-                if let Some(dep_path) =
-                    project.resolve_require(&req, &require_paths, &mut unresolved_requires)
-                {
-                    deps.push(dep_path.clone());
-                    project
-                        .require_relations
-                        .entry(path.clone())
-                        .or_insert_with(Vec::new)
-                        .push(dep_path.clone());
-                    project
-                        .reverse_require
-                        .entry(dep_path)
-                        .or_insert_with(Vec::new)
-                        .push(path.clone());
-                }
-            }
-            // 更新内存缓存
-            Self::update_cache(&mut project.cache, path, deps);
-        }
-
         // 对于仍然没有 require_relations 的文件（未分词且在 cache 中存在），恢复 cache 中的 deps
         for path in &all_lua_files {
             if project.require_relations.contains_key(path) {
@@ -263,163 +462,571 @@ impl Project {
                     project
                         .require_relations
                         .entry(path.clone())
-                        .or_insert_with(Vec::new)
+                        .or_default()
                         .push(dep.clone());
                     project
                         .reverse_require
                         .entry(dep.clone())
-                        .or_insert_with(Vec::new)
+                        .or_default()
                         .push(path.clone());
                 }
             }
         }
 
-        log_info!("Resolved require relations in {:.2?}", t1.elapsed());
+        project.report_require_cycles(diagnostics);
 
         Ok(project)
     }
 
-    /// 计算受影响集合（包含变更文件、本身依赖的文件、以及依赖它的文件；递归两方向）
-    fn compute_affected_set(
-        changed: &[PathBuf],
-        require_relations: &HashMap<PathBuf, Vec<PathBuf>>,
-        reverse_require: &HashMap<PathBuf, Vec<PathBuf>>,
-    ) -> HashSet<PathBuf> {
-        let mut visited: HashSet<PathBuf> = HashSet::new();
-        let mut stack: Vec<PathBuf> = changed.iter().cloned().collect();
+    /// 运行时的 require 关系中的每个环报告一次，给出环上的模块链。
+    /// 本次分词的文件按 require_sites，其余文件沿用缓存
+    fn report_require_cycles(&self, diagnostics: &Diagnostics) {
+        let mut edges: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+        for path in self.require_relations.keys() {
+            let requires = match self.require_sites.get(path) {
+                Some(sites) => sites
+                    .iter()
+                    .filter(|site| site.items.is_none())
+                    .map(|site| site.path.clone())
+                    .collect(),
+                None => self
+                    .cache
+                    .files
+                    .get(path)
+                    .map(|entry| entry.requires.clone())
+                    .unwrap_or_default(),
+            };
+            edges.insert(path.clone(), requires);
+        }
 
-        while let Some(p) = stack.pop() {
-            if !visited.insert(p.clone()) {
-                continue;
+        let display = |path: &Path| {
+            path.strip_prefix(&self.root)
+                .unwrap_or(path)
+                .display()
+                .to_string()
+        };
+        // from 中 require to 的位置；未分词的文件没有位置
+        let site_location = |from: &Path, to: &Path| {
+            let file = self.files.get(from)?;
+            let site = self
+                .require_sites
+                .get(from)?
+                .iter()
+                .find(|site| site.items.is_none() && site.path == to)?;
+            Some(file.location(site.span.clone()))
+        };
+        for crate::graph::Cycle {
+            path: cycle,
+            modules,
+            others,
+        } in crate::graph::find_cycles(&edges)
+        {
+            let chain: Vec<String> = cycle.iter().map(|path| display(path)).collect();
+            let mut warning = Error::new(
+                Code::RequireCycle,
+                format!("require cycle: {}", chain.join(" -> ")),
+            )
+            .with_note(
+                "a module in the cycle can see the others before they have finished loading",
+            );
+            // 每组互相 require 的模块只报告一个环，还有别的环时列出这一组的全部模块，以及环以外的 require
+            if !others.is_empty() {
+                let names: Vec<String> = modules.iter().map(|path| display(path)).collect();
+                let requires: Vec<String> = others
+                    .iter()
+                    .map(|(from, to)| format!("    '{}' requires '{}'", display(from), display(to)))
+                    .collect();
+                warning = warning.with_note(format!(
+                    "this is one of several cycles among {}; the other requires between them:\n{}",
+                    names.join(", "),
+                    requires.join("\n")
+                ));
             }
-            // 向上：谁依赖我（需要重新编译）
-            if let Some(owners) = reverse_require.get(&p) {
-                for o in owners {
-                    if !visited.contains(o) {
-                        stack.push(o.clone());
-                    }
+            warning = warning.with_help("move the shared code into a module that requires none of them, or require it inside a function");
+            if let Some(location) = site_location(&cycle[0], &cycle[1]) {
+                warning = warning.at(location);
+            }
+            for pair in cycle.windows(2).skip(1) {
+                if let Some(location) = site_location(&pair[0], &pair[1]) {
+                    warning = warning.with_label(
+                        location,
+                        format!("'{}' requires '{}'", display(&pair[0]), display(&pair[1])),
+                    );
+                }
+            }
+            diagnostics.push(Diagnostic::new(warning));
+        }
+    }
+
+    /// 目录下所有的 lua 文件
+    pub fn lua_files(root: &Path) -> Vec<PathBuf> {
+        WalkDir::new(root)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|e| {
+                e.path()
+                    .extension()
+                    .map(|ext| ext == "lua")
+                    .unwrap_or(false)
+            })
+            .map(|e| e.path().to_path_buf())
+            .collect()
+    }
+
+    /// 读取并分词 paths 中的文件，解析其中的 require，更新 require_relations / reverse_require / cache。
+    /// 返回导出的宏与缓存中记录的不同的文件
+    fn add_files(
+        &mut self,
+        paths: &[PathBuf],
+        config: &Config,
+        search_path: &SearchPath,
+        unresolved_requires: &mut HashSet<String>,
+        diagnostics: &Diagnostics,
+    ) -> HashSet<PathBuf> {
+        // 并行读取与分词，同时取得自身的全局宏的指纹；全局宏有错误的文件没有指纹，之后展开时报告
+        let files: Vec<(PathBuf, File, Option<u64>)> = paths
+            .par_iter()
+            .filter_map(|path| {
+                let mut file = File::read(path, config.dialect).ok()?;
+                let fingerprint = file
+                    .parse_global_macros()
+                    .ok()
+                    .map(|macros| macro_fingerprint(&macros));
+                Some((path.clone(), file, fingerprint))
+            })
+            .collect();
+
+        // 合并分词结果到 files（覆盖或新增）
+        let mut fingerprints: HashMap<PathBuf, Option<u64>> = HashMap::new();
+        for (path, file, fingerprint) in files {
+            diagnostics.allow(&path, file.allowed_codes());
+            self.files.insert(path.clone(), file);
+            fingerprints.insert(path, fingerprint);
+        }
+
+        let mut changed = HashSet::new();
+        // 按路径顺序处理，同一个无法解析的模块总是在同一处给出警告
+        let mut paths: Vec<&PathBuf> = fingerprints.keys().collect();
+        paths.sort();
+        for path in paths {
+            // 去掉缓存中恢复的旧依赖
+            for dep in self.require_relations.remove(path).unwrap_or_default() {
+                if let Some(dependents) = self.reverse_require.get_mut(&dep) {
+                    dependents.retain(|dependent| dependent != path);
                 }
             }
-            // 向下：我依赖的文件也可能需要重新编译 (例如宏变动会影响依赖)
-            if let Some(deps) = require_relations.get(&p) {
-                for d in deps {
-                    if !visited.contains(d) {
-                        stack.push(d.clone());
+            let file = &self.files[path];
+            let mut deps: Vec<PathBuf> = Vec::new();
+            let mut sites: Vec<RequireSite> = Vec::new();
+            let required_modules = Self::map_dynamic_requires(
+                &self.root,
+                path,
+                Self::get_required_modules(file, diagnostics),
+                &config.dynamic_requires,
+            );
+            for required in required_modules {
+                let RequiredModule {
+                    name: req,
+                    span,
+                    export,
+                    items,
+                    dynamic,
+                } = required;
+                if dynamic {
+                    diagnostics.push(Diagnostic::new(
+                        Error::new(
+                            Code::DynamicRequire,
+                            format!("skip dynamic require {} in module parsing", req),
+                        )
+                        .at(file.location(span))
+                        .with_help("declare the modules it can load with `-- @requires \"a\", \"b\"` or in 'dynamic_requires' in dlua.json"),
+                    ));
+                    continue;
+                }
+                let Some(dep_path) = search_path.resolve(&self.root, &req) else {
+                    // 每个模块只警告一次；被忽略的位置不算，其它位置仍会警告
+                    if !unresolved_requires.contains(&req)
+                        && diagnostics.push(Diagnostic::new(
+                            Error::new(
+                                Code::UnresolvedRequire,
+                                format!("Unable to resolve require '{}'", req),
+                            )
+                            .at(file.location(span)),
+                        ))
+                    {
+                        unresolved_requires.insert(req);
                     }
+                    continue;
+                };
+                deps.push(dep_path.clone());
+                sites.push(RequireSite {
+                    path: dep_path.clone(),
+                    span,
+                    export,
+                    items,
+                });
+                self.reverse_require
+                    .entry(dep_path)
+                    .or_default()
+                    .push(path.clone());
+            }
+            self.require_relations.insert(path.clone(), deps.clone());
+            let requires = sites
+                .iter()
+                .filter(|site| site.items.is_none())
+                .map(|site| site.path.clone())
+                .collect();
+
+            // 导出的宏由自身的全局宏与 `-- @export` 再导出的模块决定
+            let own = fingerprints[path];
+            let mut exports = format!("{:x}", own.unwrap_or_default());
+            for site in sites.iter().filter(|site| site.export) {
+                exports.push_str(&format!("\n{}", site.path.display()));
+                for item in site.items.iter().flatten() {
+                    exports.push_str(&format!(" {} as {}", item.name, item.alias));
                 }
             }
+            let macros = fnv1a(exports.as_bytes());
+            if own.is_none() || self.cache.files.get(path).map(|entry| entry.macros) != Some(macros)
+            {
+                changed.insert(path.clone());
+            }
+
+            self.require_sites.insert(path.clone(), sites);
+            // 更新内存缓存
+            Self::update_cache(&mut self.cache, path, &file.source, macros, deps, requires);
         }
+        changed
+    }
 
-        visited
+    /// 用 `-- @export` 再导出了 changed 中的模块的已加载文件，导出的宏同样改变了
+    fn propagate_reexports(&self, changed: &mut HashSet<PathBuf>) {
+        loop {
+            let reexporting: Vec<PathBuf> = self
+                .require_sites
+                .iter()
+                .filter(|(path, sites)| {
+                    !changed.contains(*path)
+                        && sites
+                            .iter()
+                            .any(|site| site.export && changed.contains(&site.path))
+                })
+                .map(|(path, _)| path.clone())
+                .collect();
+            if reexporting.is_empty() {
+                break;
+            }
+            changed.extend(reexporting);
+        }
     }
 
-    /// 提取 tokens 中的静态 require 模块名
-    fn get_required_modules(tokens_with_text: &Vec<TokenWithText>) -> Vec<String> {
+    /// 文件中所有 require 与 `-- @use` 的模块名、位置，以及是否被 `-- @export` 标记。
+    /// 参数不是字符串常量的 require 标记为 dynamic，由调用者决定如何处理
+    pub fn get_required_modules(file: &File, diagnostics: &Diagnostics) -> Vec<RequiredModule> {
+        let export_comment = Regex::new(r"^--\s*@export\b").unwrap();
+        let tokens = &file.tokens;
         let mut found_modules = Vec::new();
-        let mut require_found = false;
-        let mut require_left_paren = false;
-
-        for i in 0..tokens_with_text.len() {
-            let token_with_text = &tokens_with_text[i];
-            if token_with_text.kind == Token::Require {
-                require_found = true;
-            } else if require_found {
-                if token_with_text.kind == Token::LParen {
-                    require_left_paren = true;
-                } else if require_left_paren {
-                    if token_with_text.kind == Token::String {
-                        let module_name = strip_quotes(&token_with_text.text).to_string();
-                        found_modules.push(module_name);
-                    } else if token_with_text.kind == Token::Whitespace {
-                        // skip
-                    } else if token_with_text.kind == Token::RParen {
-                        require_found = false;
-                        require_left_paren = false;
-                    } else {
-                        log_warn!(
-                            "skip dynamic require {} in module parsing",
-                            token_with_text.text
-                        );
-                        require_found = false;
-                        require_left_paren = false;
+        // `-- @export` 作用于下一行的 require
+        let mut export_line: Option<usize> = None;
+        let declared = Self::declared_requires(file, diagnostics);
+
+        let mut index = 0;
+        while index < tokens.len() {
+            let token = &tokens[index];
+            index += 1;
+            if token.kind == Token::Comment && export_comment.is_match(&token.text) {
+                export_line = Some(file.line_col_from_pos(token.span.start).0 + 1);
+            } else if token.kind == Token::UseComment {
+                let line = file.line_col_from_pos(token.span.start).0;
+                match Self::parse_use(file, token) {
+                    Ok((name, span, items)) => found_modules.push(RequiredModule {
+                        name,
+                        span,
+                        export: export_line == Some(line),
+                        items: Some(items),
+                        dynamic: false,
+                    }),
+                    Err(err) => {
+                        diagnostics.push(err);
+                    }
+                }
+            } else if token.kind == Token::Require {
+                let line = file.line_col_from_pos(token.span.start).0;
+                if let Some((name, span, dynamic, end)) = Self::scan_require_call(tokens, index) {
+                    let export = export_line == Some(line);
+                    // 动态 require 换成 `-- @requires` 声明的模块
+                    let modules = match declared.get(&line) {
+                        Some(modules) if dynamic => modules.clone(),
+                        _ => vec![(name, dynamic)],
+                    };
+                    for (name, dynamic) in modules {
+                        found_modules.push(RequiredModule {
+                            name,
+                            span: span.clone(),
+                            export,
+                            items: None,
+                            dynamic,
+                        });
                     }
+                    index = end;
                 }
             }
         }
         found_modules
     }
 
-    /// 解析 require 名称到实际文件路径（支持 search_paths 中带 '?' 或不带）
-    fn resolve_require(
-        &self,
-        req: &str,
-        search_paths: &[String],
-        unresolved_requires: &mut HashSet<PathBuf>,
-    ) -> Option<PathBuf> {
-        let base_dir = &self.root;
-        #[cfg(target_os = "windows")]
-        let module_path = req.replace('.', "\\");
-        #[cfg(not(target_os = "windows"))]
-        let module_path = req.replace('.', "/");
-
-        for search_path in search_paths {
-            let candidate_base = if search_path.contains('?') {
-                PathBuf::from(search_path.replace('?', &module_path))
-            } else {
-                PathBuf::from(search_path).join(&module_path)
+    /// `-- @requires "a", "b"` 声明的模块及其作用的行：写在行尾时作用于同一行的动态 require，
+    /// 单独成行时作用于下一行
+    fn declared_requires(
+        file: &File,
+        diagnostics: &Diagnostics,
+    ) -> HashMap<usize, Vec<(String, bool)>> {
+        let annotation = Regex::new(r"^--\s*@requires\b(.*)$").unwrap();
+        let module = Regex::new(r#"^\s*(?:"([^"]*)"|'([^']*)')\s*$"#).unwrap();
+        let mut declared = HashMap::new();
+        for token in file.tokens.iter().filter(|t| t.kind == Token::Comment) {
+            let Some(caps) = annotation.captures(&token.text) else {
+                continue;
             };
-
-            let candidate_with_ext = base_dir.join(&candidate_base).with_extension("lua");
-            if candidate_with_ext.exists() {
-                return Some(candidate_with_ext);
+            let modules: Option<Vec<(String, bool)>> = caps[1]
+                .split(',')
+                .map(|part| {
+                    let m = module.captures(part)?;
+                    Some((m.get(1).or(m.get(2))?.as_str().to_string(), false))
+                })
+                .collect();
+            match modules {
+                Some(modules) => {
+                    let (line, column) = file.line_col_from_pos(token.span.start);
+                    let line_start = token.span.start + 1 - column;
+                    let standalone = file.source[line_start..token.span.start].trim().is_empty();
+                    declared.insert(if standalone { line + 1 } else { line }, modules);
+                }
+                None => {
+                    diagnostics.push(
+                        file.error_at(
+                            Code::MalformedRequires,
+                            token.span.clone(),
+                            format!("malformed requires annotation '{}'", token.text),
+                        )
+                        .with_help("list the modules as strings: `-- @requires \"a\", \"b\"`"),
+                    );
+                }
             }
+        }
+        declared
+    }
 
-            let candidate_no_ext = base_dir.join(&candidate_base);
-            if candidate_no_ext.exists() {
-                return Some(candidate_no_ext);
+    /// 按 dlua.json 中的 dynamic_requires 把动态 require 换成它可能加载的模块，没有匹配的规则时保留
+    pub fn map_dynamic_requires(
+        root: &Path,
+        path: &Path,
+        required: Vec<RequiredModule>,
+        table: &DynamicRequires,
+    ) -> Vec<RequiredModule> {
+        let relative = path
+            .strip_prefix(root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .replace('\\', "/");
+        let mut mapped = Vec::new();
+        for module in required {
+            match table.modules(&relative, &module.name) {
+                Some(names) if module.dynamic => {
+                    mapped.extend(names.iter().map(|name| RequiredModule {
+                        name: name.clone(),
+                        dynamic: false,
+                        ..module.clone()
+                    }));
+                }
+                _ => mapped.push(module),
             }
         }
+        mapped
+    }
 
-        if !unresolved_requires.contains(&PathBuf::from(&module_path)) {
-            unresolved_requires.insert(PathBuf::from(&module_path));
-            log_warn!("Unable to resolve require '{}'", module_path);
+    /// 解析 start 处开始的 `(参数)`，返回模块名或参数表达式、其位置、是否是动态 require，以及右括号之后的下标
+    fn scan_require_call(
+        tokens: &[TokenWithText],
+        start: usize,
+    ) -> Option<(String, Range<usize>, bool, usize)> {
+        let is_trivia = |t: &TokenWithText| matches!(t.kind, Token::Whitespace | Token::Comment);
+        let open = start + tokens[start..].iter().position(|t| !is_trivia(t))?;
+        if tokens[open].kind != Token::LParen {
+            return None;
+        }
+        let mut depth = 0usize;
+        let mut args: Vec<&TokenWithText> = Vec::new();
+        for (index, token) in tokens.iter().enumerate().skip(open + 1) {
+            match token.kind {
+                Token::LParen | Token::LBracket | Token::LBrace => depth += 1,
+                Token::RParen if depth == 0 => {
+                    let first = args.iter().position(|t| !is_trivia(t))?;
+                    let last = args.iter().rposition(|t| !is_trivia(t))?;
+                    let args = &args[first..=last];
+                    let span = args[0].span.start..args[args.len() - 1].span.end;
+                    if let [arg] = args
+                        && arg.kind == Token::String
+                    {
+                        return Some((strip_quotes(&arg.text).to_string(), span, false, index + 1));
+                    }
+                    let expression: String = args.iter().map(|t| t.text.as_str()).collect();
+                    return Some((expression, span, true, index + 1));
+                }
+                Token::RParen | Token::RBracket | Token::RBrace => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+            args.push(token);
         }
         None
     }
 
-    /// 从磁盘加载缓存（JSON），失败则返回空缓存
-    fn load_cache(path: &str) -> BuildCache {
-        if let Ok(data) = fs::read_to_string(path) {
-            if let Ok(cache) = serde_json::from_str(&data) {
-                return cache;
-            }
+    /// 解析 `-- @use module { A, B as c }`，返回模块名、模块名的位置与列出的宏。
+    /// 模块名可以加引号，如 `-- @use "lib.macros" { A }`
+    fn parse_use(
+        file: &File,
+        token: &TokenWithText,
+    ) -> Result<(String, Range<usize>, Vec<UseItem>), Error> {
+        let directive = Regex::new(
+            r#"^--\s*@use\s+(?:"([^"]*)"|'([^']*)'|([A-Za-z_][A-Za-z0-9_.]*))\s*\{([^}]*)\}\s*$"#,
+        )
+        .unwrap();
+        let item_pattern =
+            Regex::new(r"^([A-Za-z_][A-Za-z0-9_]*)(?:\s+as\s+([A-Za-z_][A-Za-z0-9_]*))?$").unwrap();
+        let start = token.span.start;
+        let malformed = |span: Range<usize>, message: String| {
+            file.error_at(Code::MalformedUse, span, message)
+                .with_help("write it as `-- @use module { A, B as b }`")
+        };
+        let Some(caps) = directive.captures(&token.text) else {
+            return Err(malformed(
+                token.span.clone(),
+                format!("malformed use directive '{}'", token.text),
+            ));
+        };
+        let module = caps.get(1).or(caps.get(2)).or(caps.get(3)).unwrap();
+        let module_span = start + module.start()..start + module.end();
+        let list = caps.get(4).unwrap();
+        let mut parts: Vec<(usize, &str)> = Vec::new();
+        let mut offset = start + list.start();
+        for part in list.as_str().split(',') {
+            parts.push((offset, part));
+            offset += part.len() + 1;
         }
-        BuildCache {
-            files: HashMap::new(),
+        // 允许末尾的逗号
+        if parts.len() > 1 && parts.last().is_some_and(|(_, part)| part.trim().is_empty()) {
+            parts.pop();
+        }
+        let mut items = Vec::new();
+        for (offset, part) in parts {
+            let trimmed = part.trim();
+            let item_start = offset + part.len() - part.trim_start().len();
+            let item_span = item_start..item_start + trimmed.len();
+            let Some(item) = item_pattern.captures(trimmed) else {
+                return Err(malformed(
+                    item_span,
+                    format!(
+                        "expected a macro name or 'NAME as alias' in use directive, found '{}'",
+                        trimmed
+                    ),
+                ));
+            };
+            let name = item[1].to_string();
+            let alias = item
+                .get(2)
+                .map_or_else(|| name.clone(), |a| a.as_str().to_string());
+            items.push(UseItem {
+                name,
+                alias,
+                span: item_span,
+            });
         }
+        Ok((module.as_str().to_string(), module_span, items))
     }
 
-    /// 获取文件的 mtime（秒），失败返回 0
-    fn get_mtime(path: &PathBuf) -> u64 {
-        fs::metadata(path)
-            .and_then(|meta| meta.modified())
+    /// 从磁盘加载缓存（JSON）。读取失败，或格式版本、dlua 版本、配置与本次构建不同时返回空缓存，
+    /// 所有文件都会重新处理
+    fn load_cache(path: &str, config_hash: u64) -> BuildCache {
+        let fresh = BuildCache::new(config_hash);
+        let Ok(data) = fs::read_to_string(path) else {
+            return fresh;
+        };
+        let Ok(cache) = serde_json::from_str::<BuildCache>(&data) else {
+            log_verbose!(
+                "Ignoring unreadable build cache {}, rebuilding all files",
+                path
+            );
+            return fresh;
+        };
+        if cache.version != fresh.version {
+            log_verbose!(
+                "Build cache format changed ({} -> {}), rebuilding all files",
+                cache.version,
+                fresh.version
+            );
+        } else if cache.dlua_version != fresh.dlua_version {
+            log_verbose!(
+                "dlua version changed ({} -> {}), rebuilding all files",
+                cache.dlua_version,
+                fresh.dlua_version
+            );
+        } else if cache.config_hash != fresh.config_hash {
+            log_verbose!("Configuration changed, rebuilding all files");
+        } else {
+            return cache;
+        }
+        fresh
+    }
+
+    /// 文件的修改时间（纳秒）与大小，失败返回 (0, 0)
+    fn file_stamp(path: &Path) -> (u64, u64) {
+        let Ok(meta) = fs::metadata(path) else {
+            return (0, 0);
+        };
+        let mtime = meta
+            .modified()
             .ok()
             .and_then(|mtime| mtime.duration_since(SystemTime::UNIX_EPOCH).ok())
-            .map(|dur| dur.as_secs())
-            .unwrap_or(0)
+            .map(|dur| dur.as_nanos() as u64)
+            .unwrap_or(0);
+        (mtime, meta.len())
     }
 
-    /// 找出需要重新分词/分析的文件（基于 mtime）
-    fn find_changed_files(all_files: &Vec<PathBuf>, cache: &BuildCache) -> Vec<PathBuf> {
+    /// 找出需要重新分词/分析的文件：修改时间与大小都没变的直接跳过，否则比较内容的哈希。
+    /// 只是修改时间改变的文件不需要处理，同时更新缓存中的修改时间与大小
+    fn find_changed_files(all_files: &[PathBuf], cache: &mut BuildCache) -> Vec<PathBuf> {
+        let changes: Vec<(PathBuf, Change)> = all_files
+            .par_iter()
+            .filter_map(|path| {
+                let Some(file_cache) = cache.files.get(path) else {
+                    return Some((path.clone(), Change::Modified));
+                };
+                let (mtime, size) = Self::file_stamp(path);
+                if file_cache.mtime == mtime && file_cache.size == size {
+                    return None;
+                }
+                let change = match fs::read(path) {
+                    Ok(content)
+                        if size == file_cache.size && fnv1a(&content) == file_cache.hash =>
+                    {
+                        Change::Touched { mtime, size }
+                    }
+                    _ => Change::Modified,
+                };
+                Some((path.clone(), change))
+            })
+            .collect();
+
         let mut changed = Vec::new();
-        for path in all_files {
-            let mtime = Self::get_mtime(path);
-            if let Some(file_cache) = cache.files.get(path) {
-                if file_cache.mtime != mtime {
-                    changed.push(path.clone());
+        for (path, change) in changes {
+            match change {
+                Change::Modified => changed.push(path),
+                Change::Touched { mtime, size } => {
+                    if let Some(file_cache) = cache.files.get_mut(&path) {
+                        file_cache.mtime = mtime;
+                        file_cache.size = size;
+                    }
                 }
-            } else {
-                changed.push(path.clone());
             }
         }
         changed
@@ -432,14 +1039,50 @@ impl Project {
         }
     }
 
-    /// 更新内存缓存（不立即写盘）
-    fn update_cache(cache: &mut BuildCache, file: &PathBuf, deps: Vec<PathBuf>) {
-        let mtime = Self::get_mtime(file);
-        cache.files.insert(file.clone(), FileCache { mtime, deps });
+    /// 本次处理的文件没有成功输出时调用：从缓存中移除它们，下次构建会重新处理
+    pub fn discard_processed_files(&mut self) {
+        for path in self.files.keys() {
+            self.cache.files.remove(path);
+        }
+    }
+
+    /// 更新内存缓存（不立即写盘），source 是分词时读到的内容
+    fn update_cache(
+        cache: &mut BuildCache,
+        file: &Path,
+        source: &str,
+        macros: u64,
+        deps: Vec<PathBuf>,
+        requires: Vec<PathBuf>,
+    ) {
+        let (mtime, size) = Self::file_stamp(file);
+        let empty = cache.files.get(file).is_some_and(|entry| entry.empty);
+        cache.files.insert(
+            file.to_path_buf(),
+            FileCache {
+                mtime,
+                size,
+                hash: fnv1a(source.as_bytes()),
+                macros,
+                deps,
+                requires,
+                empty,
+            },
+        );
+    }
+}
+
+/// 64 位 FNV-1a 哈希
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
     }
+    hash
 }
 
-fn compute_line_starts(src: &str) -> Vec<usize> {
+pub fn compute_line_starts(src: &str) -> Vec<usize> {
     let mut starts = Vec::with_capacity(128);
     starts.push(0);
     for (i, &b) in src.as_bytes().iter().enumerate() {
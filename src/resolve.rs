@@ -0,0 +1,261 @@
+use regex::Regex;
+use serde_json::Value;
+use std::path::{Component, Path, PathBuf};
+
+use crate::{
+    Config,
+    code::Code,
+    error::{Error, Result},
+    severity::glob_to_regex,
+};
+
+/// 按 Lua `package.path` 的语义查找模块。
+///
+/// require_paths 中的每一项都可以是 `;` 分隔的模板列表，模板中的 `?` 替换为模块路径
+/// （模块名中的 `.` 换成 `/`），按顺序取第一个存在的文件；`;;` 处插入默认路径 [`SearchPath::DEFAULT`]。
+/// 不含 `?` 的项视为目录，等价于 `dir/?.lua;dir/?/init.lua`。相对路径相对于源码根目录
+#[derive(Debug, Clone)]
+pub struct SearchPath {
+    pub templates: Vec<String>,
+}
+
+impl SearchPath {
+    /// Lua 默认路径中位于项目内的部分
+    pub const DEFAULT: &'static str = "./?.lua;./?/init.lua";
+
+    pub fn new(require_paths: &[String]) -> Self {
+        let mut templates = Vec::new();
+        for entry in require_paths {
+            if !entry.contains('?') && !entry.contains(';') {
+                let dir = entry.trim_end_matches(['/', '\\']);
+                let dir = if dir.is_empty() { "." } else { dir };
+                templates.push(format!("{}/?.lua", dir));
+                templates.push(format!("{}/?/init.lua", dir));
+                continue;
+            }
+            // ;; 即两个模板之间的空项，在这里插入默认路径
+            let expanded = entry.replace(";;", &format!(";{};", Self::DEFAULT));
+            templates.extend(
+                expanded
+                    .split(';')
+                    .filter(|t| !t.is_empty())
+                    .map(str::to_string),
+            );
+        }
+        if templates.is_empty() {
+            templates = Self::DEFAULT.split(';').map(str::to_string).collect();
+        }
+        SearchPath { templates }
+    }
+
+    /// 依次尝试的文件路径
+    pub fn candidates(&self, root: &Path, module: &str) -> Vec<PathBuf> {
+        let module_path = module.replace('.', "/");
+        self.templates
+            .iter()
+            .map(|template| root.join(normalize(Path::new(&template.replace('?', &module_path)))))
+            .collect()
+    }
+
+    /// 检查模板都在源码根目录之内：根目录之外的文件不会被处理，这样的模板永远匹配不到
+    pub fn check(&self) -> Result<()> {
+        for template in &self.templates {
+            if normalize(Path::new(template)).starts_with("..") {
+                return Err(Error::new(
+                    Code::Config,
+                    format!(
+                        "require path '{}' in dlua.json points outside the source root",
+                        template
+                    ),
+                )
+                .with_help(
+                    "require paths are relative to the source root; move the modules into it",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// 第一个存在的候选文件
+    pub fn resolve(&self, root: &Path, module: &str) -> Option<PathBuf> {
+        self.candidates(root, module)
+            .into_iter()
+            .find(|candidate| candidate.is_file())
+    }
+}
+
+/// 去掉模板中的 `.`，`..` 与它前面的一级目录相互抵消。根目录保持原样，
+/// 与遍历目录得到的路径一样以根目录开头，根目录为 `.` 时两边都是 `./a.lua`
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// `dlua resolve <module> [root]`：列出模块的每个候选路径及是否存在，以及最终匹配的文件。
+/// 找不到时返回 DL0001 错误，候选路径的列表作为附注
+pub fn run(config: &Config, module: &str) -> Result<String> {
+    let search_path = SearchPath::new(config.require_paths.as_deref().unwrap_or_default());
+    let mut found: Option<PathBuf> = None;
+    let mut candidates = String::new();
+    for (i, candidate) in search_path
+        .candidates(&config.entry, module)
+        .into_iter()
+        .enumerate()
+    {
+        let exists = candidate.is_file();
+        let status = match (exists, &found) {
+            (true, None) => "found",
+            (true, Some(_)) => "found (shadowed)",
+            (false, _) => "not found",
+        };
+        candidates.push_str(&format!(
+            "  {:>2}. {:<24} {}  {}\n",
+            i + 1,
+            search_path.templates[i],
+            candidate.display(),
+            status
+        ));
+        if exists && found.is_none() {
+            found = Some(candidate);
+        }
+    }
+    match found {
+        Some(path) => Ok(format!(
+            "Resolving '{}' in {}\n{}=> {}\n",
+            module,
+            config.entry.display(),
+            candidates,
+            path.display()
+        )),
+        None => Err(Error::new(
+            Code::UnresolvedRequire,
+            format!(
+                "module '{}' not found in {}",
+                module,
+                config.entry.display()
+            ),
+        )
+        .with_note(format!("tried:\n{}", candidates.trim_end()))),
+    }
+}
+
+/// dlua.json 中 dynamic_requires 的一条规则：匹配的文件中，参数与 expression 匹配的动态 require
+/// 可能加载 modules 中的任意一个模块
+#[derive(Debug, Clone)]
+pub struct DynamicRule {
+    // 相对于源码根目录的 glob，没有时对所有文件生效
+    pub paths: Vec<Regex>,
+    pub expression: Regex,
+    pub modules: Vec<String>,
+}
+
+/// 动态 require 与它可能加载的模块的对应表：
+///
+/// ```json
+/// {
+///   "dynamic_requires": [
+///     { "paths": ["levels/**"], "expression": "\"levels.\" .. *", "modules": ["levels.forest", "levels.cave"] }
+///   ]
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DynamicRequires {
+    // 按顺序取第一条匹配的规则
+    pub rules: Vec<DynamicRule>,
+}
+
+impl DynamicRequires {
+    pub fn from_json(config: &Value) -> Result<Self> {
+        let Some(rules) = config.get("dynamic_requires") else {
+            return Ok(DynamicRequires::default());
+        };
+        let rules = rules
+            .as_array()
+            .ok_or_else(|| config_error("'dynamic_requires' in dlua.json must be an array"))?;
+        let mut table = DynamicRequires::default();
+        for rule in rules {
+            let strings = |key: &str| -> Result<Vec<String>> {
+                match rule.get(key) {
+                    None => Ok(Vec::new()),
+                    Some(Value::Array(items)) => items
+                        .iter()
+                        .map(|item| {
+                            item.as_str().map(str::to_string).ok_or_else(|| {
+                                config_error(format!(
+                                    "'{}' in 'dynamic_requires' must be an array of strings",
+                                    key
+                                ))
+                            })
+                        })
+                        .collect(),
+                    Some(_) => Err(config_error(format!(
+                        "'{}' in 'dynamic_requires' must be an array of strings",
+                        key
+                    ))),
+                }
+            };
+            let expression = rule
+                .get("expression")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| {
+                    config_error("each entry of 'dynamic_requires' needs an 'expression' string")
+                })?;
+            let modules = strings("modules")?;
+            if modules.is_empty() {
+                return Err(config_error(format!(
+                    "the entry for '{}' in 'dynamic_requires' lists no modules",
+                    expression
+                )));
+            }
+            table.rules.push(DynamicRule {
+                paths: strings("paths")?.iter().map(|p| glob_to_regex(p)).collect(),
+                expression: expression_to_regex(expression),
+                modules,
+            });
+        }
+        Ok(table)
+    }
+
+    /// relative_path 中参数为 expression 的动态 require 可能加载的模块
+    pub fn modules(&self, relative_path: &str, expression: &str) -> Option<&[String]> {
+        let expression: String = expression.split_whitespace().collect();
+        self.rules
+            .iter()
+            .find(|rule| {
+                (rule.paths.is_empty() || rule.paths.iter().any(|p| p.is_match(relative_path)))
+                    && rule.expression.is_match(&expression)
+            })
+            .map(|rule| rule.modules.as_slice())
+    }
+}
+
+/// 表达式的模式转为正则：忽略空白，`*` 匹配任意文本，其余字符按原样匹配
+fn expression_to_regex(pattern: &str) -> Regex {
+    let mut regex = String::from("^");
+    for ch in pattern.chars().filter(|c| !c.is_whitespace()) {
+        match ch {
+            '*' => regex.push_str(".*"),
+            _ => regex.push_str(&regex::escape(&ch.to_string())),
+        }
+    }
+    regex.push('$');
+    Regex::new(&regex).unwrap()
+}
+
+fn config_error(message: impl Into<String>) -> Error {
+    Error::new(Code::Config, message)
+}
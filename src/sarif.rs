@@ -0,0 +1,116 @@
+use serde_json::{Value, json};
+use std::path::Path;
+
+use crate::{
+    code::Code,
+    diagnostics::Diagnostic,
+    error::{Error, Location, Result},
+};
+
+/// 把一次构建的诊断转换为 SARIF 2.1.0 日志，供代码扫描面板使用
+pub fn to_sarif(diagnostics: &[Diagnostic]) -> Value {
+    let mut rules: Vec<Code> = diagnostics.iter().map(|d| d.error.code).collect();
+    rules.sort_by_key(|code| code.as_str());
+    rules.dedup();
+
+    let results: Vec<Value> = diagnostics
+        .iter()
+        .map(|d| {
+            let mut result = json!({
+                "ruleId": d.error.code.as_str(),
+                "level": d.severity.name(),
+                "message": { "text": message_text(d) },
+            });
+            if let Some(location) = &d.error.location {
+                result["locations"] = json!([{ "physicalLocation": physical_location(location) }]);
+            }
+            if !d.error.labels.is_empty() {
+                result["relatedLocations"] = d
+                    .error
+                    .labels
+                    .iter()
+                    .enumerate()
+                    .map(|(id, label)| {
+                        json!({
+                            "id": id,
+                            "physicalLocation": physical_location(&label.location),
+                            "message": { "text": label.message },
+                        })
+                    })
+                    .collect();
+            }
+            result
+        })
+        .collect();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "dlua",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules.iter().map(|code| json!({
+                        "id": code.as_str(),
+                        "shortDescription": { "text": code.summary() },
+                    })).collect::<Vec<_>>(),
+                }
+            },
+            "results": results,
+        }]
+    })
+}
+
+/// 写入 `--sarif <file>` 指定的文件
+pub fn write(path: &Path, diagnostics: &[Diagnostic]) -> Result<()> {
+    let data = serde_json::to_string_pretty(&to_sarif(diagnostics)).unwrap();
+    std::fs::write(path, data).map_err(|err| {
+        Error::new(
+            Code::Io,
+            format!("failed to write SARIF report {}: {}", path.display(), err),
+        )
+    })
+}
+
+/// SARIF 的结果只有一段文本，宏名、note 与 help 附在消息之后
+fn message_text(d: &Diagnostic) -> String {
+    let mut text = d.error.message.clone();
+    if let Some(name) = &d.error.macro_name {
+        text.push_str(&format!(" (in expansion of macro {})", name));
+    }
+    for note in &d.error.notes {
+        text.push_str(&format!("\nnote: {}", note));
+    }
+    if let Some(help) = &d.error.help {
+        text.push_str(&format!("\nhelp: {}", help));
+    }
+    text
+}
+
+fn physical_location(location: &Location) -> Value {
+    json!({
+        "artifactLocation": { "uri": file_uri(&location.path) },
+        "region": {
+            "startLine": location.line,
+            "startColumn": location.column,
+            "endLine": location.end_line,
+            "endColumn": location.end_column,
+        }
+    })
+}
+
+/// 绝对路径转为 file:// URI，路径分隔符统一为 /
+fn file_uri(path: &Path) -> String {
+    let path = path
+        .display()
+        .to_string()
+        .replace('\\', "/")
+        .replace(' ', "%20");
+    let path = path.strip_prefix("//?/").unwrap_or(&path);
+    if path.starts_with('/') {
+        format!("file://{}", path)
+    } else {
+        format!("file:///{}", path)
+    }
+}
@@ -0,0 +1,190 @@
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::{
+    code::Code,
+    error::{Error, Result},
+};
+
+/// dlua.json 中为某个编号指定的级别
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    /// 不报告
+    Allow,
+    Warning,
+    Error,
+}
+
+impl Level {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Allow => "allow",
+            Level::Warning => "warning",
+            Level::Error => "error",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "allow" => Some(Level::Allow),
+            "warning" => Some(Level::Warning),
+            "error" => Some(Level::Error),
+            _ => None,
+        }
+    }
+}
+
+/// 只对匹配的路径生效的调整
+#[derive(Debug, Clone)]
+pub struct PathOverride {
+    // 相对于源码根目录的 glob，编译为正则
+    pub patterns: Vec<Regex>,
+    pub levels: HashMap<Code, Level>,
+}
+
+/// 按编号与路径调整诊断的严重程度：
+///
+/// ```json
+/// {
+///   "diagnostics": { "DL0002": "error" },
+///   "diagnostic_overrides": [
+///     { "paths": ["legacy/**"], "diagnostics": { "DL0001": "allow" } }
+///   ]
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SeverityConfig {
+    pub levels: HashMap<Code, Level>,
+    // 后面的优先
+    pub overrides: Vec<PathOverride>,
+}
+
+impl SeverityConfig {
+    /// 读取 dlua.json 中的 diagnostics 与 diagnostic_overrides
+    pub fn from_json(config: &Value) -> Result<Self> {
+        let mut severity = SeverityConfig::default();
+        if let Some(levels) = config.get("diagnostics") {
+            severity.levels = parse_levels(levels, "diagnostics")?;
+        }
+        if let Some(overrides) = config.get("diagnostic_overrides") {
+            let overrides = overrides.as_array().ok_or_else(|| {
+                config_error("'diagnostic_overrides' in dlua.json must be an array")
+            })?;
+            for entry in overrides {
+                let paths = entry
+                    .get("paths")
+                    .and_then(|v| v.as_array())
+                    .ok_or_else(|| {
+                        config_error("each entry of 'diagnostic_overrides' needs a 'paths' array")
+                    })?;
+                let patterns = paths
+                    .iter()
+                    .map(|p| {
+                        p.as_str().map(glob_to_regex).ok_or_else(|| {
+                            config_error("'paths' in 'diagnostic_overrides' must be strings")
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let levels = match entry.get("diagnostics") {
+                    Some(levels) => parse_levels(levels, "diagnostic_overrides")?,
+                    None => HashMap::new(),
+                };
+                severity.overrides.push(PathOverride { patterns, levels });
+            }
+        }
+        Ok(severity)
+    }
+
+    /// 编号在某个文件中的级别，relative_path 是相对于源码根目录、以 / 分隔的路径
+    pub fn level(&self, code: Code, relative_path: Option<&str>) -> Option<Level> {
+        if let Some(path) = relative_path {
+            for entry in self.overrides.iter().rev() {
+                if let Some(level) = entry.levels.get(&code)
+                    && entry.patterns.iter().any(|p| p.is_match(path))
+                {
+                    return Some(*level);
+                }
+            }
+        }
+        self.levels.get(&code).copied()
+    }
+
+    /// 与 HashMap 顺序无关的文本形式，用于计算配置的指纹
+    pub fn fingerprint_key(&self) -> String {
+        let mut key = levels_key(&self.levels);
+        for entry in &self.overrides {
+            let patterns: Vec<&str> = entry.patterns.iter().map(|p| p.as_str()).collect();
+            key.push_str(&format!(
+                "|{}:{}",
+                patterns.join(","),
+                levels_key(&entry.levels)
+            ));
+        }
+        key
+    }
+}
+
+fn levels_key(levels: &HashMap<Code, Level>) -> String {
+    let mut levels: Vec<String> = levels
+        .iter()
+        .map(|(code, level)| format!("{}={}", code.as_str(), level.name()))
+        .collect();
+    levels.sort();
+    levels.join(",")
+}
+
+fn parse_levels(value: &Value, key: &str) -> Result<HashMap<Code, Level>> {
+    let map = value
+        .as_object()
+        .ok_or_else(|| config_error(format!("'{}' in dlua.json must be an object", key)))?;
+    let mut levels = HashMap::new();
+    for (name, level) in map {
+        let code = Code::parse(name).ok_or_else(|| {
+            config_error(format!("Unknown diagnostic code '{}' in '{}'", name, key))
+        })?;
+        let level = level.as_str().and_then(Level::from_name).ok_or_else(|| {
+            config_error(format!(
+                "Invalid level for {} in '{}', expected one of allow, warning, error",
+                name, key
+            ))
+        })?;
+        if level != Level::Error && !code.can_demote() {
+            return Err(config_error(format!(
+                "{} is always an error and cannot be set to '{}'",
+                name,
+                level.name()
+            )));
+        }
+        levels.insert(code, level);
+    }
+    Ok(levels)
+}
+
+fn config_error(message: impl Into<String>) -> Error {
+    Error::new(Code::Config, message)
+}
+
+/// 把 glob 转为正则：`**` 匹配任意层目录，`*` 与 `?` 不跨越 /
+pub fn glob_to_regex(glob: &str) -> Regex {
+    let mut pattern = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    pattern.push_str("(?:.*/)?");
+                } else {
+                    pattern.push_str(".*");
+                }
+            }
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            _ => pattern.push_str(&regex::escape(&ch.to_string())),
+        }
+    }
+    pattern.push('$');
+    Regex::new(&pattern).unwrap()
+}
@@ -0,0 +1,162 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::project::{File, compute_line_starts};
+
+/// 输出文件到源码的位置映射，作为 `<name>.lua.map` 写在输出文件旁边
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SourceMap {
+    pub version: u32,
+    // 源码文件的绝对路径
+    pub source: String,
+    // 按输出位置递增排列
+    pub mappings: Vec<Mapping>,
+}
+
+/// 从输出的 line:column 开始（直到下一条映射之前）的文本来自源码的 source_line:source_column。
+/// 行列号都从 1 开始；位于宏展开结果中时，源码位置是宏调用处，并记录宏名
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Mapping {
+    pub line: usize,
+    pub column: usize,
+    pub source_line: usize,
+    pub source_column: usize,
+    #[serde(rename = "macro", default, skip_serializing_if = "Option::is_none")]
+    pub macro_name: Option<String>,
+}
+
+/// 输出中一个位置对应的源码位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lookup<'a> {
+    pub source_line: usize,
+    // 查找时给出了列号才有
+    pub source_column: Option<usize>,
+    // 位置所在的宏展开
+    pub macro_name: Option<&'a str>,
+    // 只按行查找、且这一行不全是同一个宏展开时，行中各个宏展开的宏名与调用处的源码列号
+    pub expansions: Vec<(&'a str, usize)>,
+}
+
+impl SourceMap {
+    pub const VERSION: u32 = 1;
+
+    /// 由展开时记录的输出来源生成映射。每段输出的开头以及其中每一行的行首各有一条映射，
+    /// 这样按行查找时总能找到准确的源码行
+    pub fn from_file(file: &File) -> Self {
+        let output_line_starts = compute_line_starts(&file.output);
+        let mut mappings: Vec<Mapping> = Vec::new();
+        let mut push = |offset: usize| {
+            let Some((pos, macro_name)) = file.source_for_output(offset) else {
+                return;
+            };
+            let line = output_line_starts.partition_point(|&start| start <= offset);
+            let column = offset - output_line_starts[line - 1] + 1;
+            let (source_line, source_column) = file.line_col_from_pos(pos);
+            mappings.push(Mapping {
+                line,
+                column,
+                source_line,
+                source_column,
+                macro_name: macro_name.map(str::to_string),
+            });
+        };
+
+        for (i, origin) in file.origins.iter().enumerate() {
+            let end = file
+                .origins
+                .get(i + 1)
+                .map_or(file.output.len(), |next| next.output_start);
+            if origin.output_start == end {
+                continue;
+            }
+            let text = &file.output[origin.output_start..end];
+            // 行尾只有空白的一段不含代码，不为它生成映射，免得按行查找时把整行来自宏展开的行当作混合的行
+            let at_line_start = output_line_starts
+                .binary_search(&origin.output_start)
+                .is_ok();
+            let trailing_blank = text.split('\n').next().unwrap().trim().is_empty();
+            if at_line_start || !trailing_blank {
+                push(origin.output_start);
+            }
+            for (idx, _) in text.match_indices('\n') {
+                // 段末尾的换行之后是下一段的开头，由下一段负责
+                if idx + 1 < text.len() {
+                    push(origin.output_start + idx + 1);
+                }
+            }
+        }
+
+        SourceMap {
+            version: Self::VERSION,
+            source: file
+                .path
+                .canonicalize()
+                .unwrap_or(file.path.clone())
+                .display()
+                .to_string(),
+            mappings,
+        }
+    }
+
+    /// 输出中 line 行（以及 column 列）对应的源码位置。每条映射的范围到下一条映射之前为止。
+    ///
+    /// 给出列号时取该列所在的一段。只有行号时取行首所在的一段，整行都来自同一个宏展开时才算在宏展开中，
+    /// 否则列出这一行中的宏展开及其调用处的列号；没有映射的行（如多行的宏展开结果中间）沿用之前最近的一段
+    pub fn lookup(&self, line: usize, column: Option<usize>) -> Option<Lookup<'_>> {
+        let end = self.mappings.partition_point(|m| m.line <= line);
+        let begin = self.mappings[..end].partition_point(|m| m.line < line);
+        let on_line = if begin < end {
+            &self.mappings[begin..end]
+        } else {
+            &self.mappings[end.checked_sub(1)?..end]
+        };
+
+        if let Some(column) = column {
+            let idx = on_line
+                .partition_point(|m| m.line < line || m.column <= column)
+                .saturating_sub(1);
+            let mapping = &on_line[idx];
+            let source_column = match (&mapping.macro_name, mapping.line == line) {
+                // 原样输出的文本逐字对应源码
+                (None, true) => mapping.source_column + column.saturating_sub(mapping.column),
+                _ => mapping.source_column,
+            };
+            return Some(Lookup {
+                source_line: mapping.source_line,
+                source_column: Some(source_column),
+                macro_name: mapping.macro_name.as_deref(),
+                expansions: Vec::new(),
+            });
+        }
+
+        let first = &on_line[0];
+        let whole_line = first.macro_name.as_deref().filter(|name| {
+            on_line
+                .iter()
+                .all(|m| m.macro_name.as_deref() == Some(*name))
+        });
+        let mut expansions: Vec<(&str, usize)> = Vec::new();
+        if whole_line.is_none() {
+            for m in on_line {
+                if let Some(name) = m.macro_name.as_deref()
+                    && !expansions.contains(&(name, m.source_column))
+                {
+                    expansions.push((name, m.source_column));
+                }
+            }
+        }
+        Some(Lookup {
+            source_line: first.source_line,
+            source_column: None,
+            macro_name: whole_line,
+            expansions,
+        })
+    }
+
+    /// 源码映射文件的路径：输出文件名后加 .map
+    pub fn path_for(out_path: &Path) -> std::path::PathBuf {
+        let mut name = out_path.as_os_str().to_owned();
+        name.push(".map");
+        name.into()
+    }
+}
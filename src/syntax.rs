@@ -0,0 +1,803 @@
+use crate::token::{Dialect, Token, TokenWithText, split_interpolation, tokenize};
+
+/// 语法错误：相对于被检查文本的字节偏移，以及错误描述
+#[derive(Debug, Clone)]
+pub struct SyntaxError {
+    pub offset: usize,
+    pub message: String,
+}
+
+/// 按给定方言检查一段 Lua 源码的语法，只判断是否合法，不构建语法树
+pub fn check(src: &str, dialect: Dialect) -> Result<(), SyntaxError> {
+    let mut parser = Parser::new(tokenize(src, dialect), src.len(), dialect);
+    parser.block()?;
+    if !parser.finished() {
+        return Err(parser.error_near("'<eof>' expected"));
+    }
+    Ok(())
+}
+
+type ParseResult<T = ()> = Result<T, SyntaxError>;
+
+/// 后缀表达式的种类，用于区分赋值目标和函数调用语句
+#[derive(PartialEq)]
+enum SuffixedKind {
+    Assignable,
+    Call,
+    Other,
+}
+
+const RESERVED_WORDS: &[&str] = &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if", "in",
+    "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+struct Parser {
+    tokens: Vec<TokenWithText>,
+    pos: usize,
+    // 文本末尾的偏移，用于报告 <eof> 处的错误
+    eof: usize,
+    dialect: Dialect,
+}
+
+impl Parser {
+    fn new(tokens: Vec<TokenWithText>, eof: usize, dialect: Dialect) -> Self {
+        // 去掉空白与注释，并把 a.b:c 这样的广义标识符拆成名字与 . : 运算符
+        let mut significant = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            match token.kind {
+                Token::Whitespace
+                | Token::Comment
+                | Token::MacroComment
+                | Token::AliasComment
+                | Token::UseComment
+                | Token::IfComment
+                | Token::EndIfComment => {}
+                Token::Ident if token.text.contains(['.', ':']) => {
+                    let mut start = 0;
+                    for (idx, ch) in token.text.char_indices() {
+                        if ch != '.' && ch != ':' {
+                            continue;
+                        }
+                        if start < idx {
+                            significant.push(sub_token(&token, Token::Ident, start..idx));
+                        }
+                        significant.push(sub_token(&token, Token::Operator, idx..idx + 1));
+                        start = idx + 1;
+                    }
+                    // 词法分析不回溯，`a. b` 中的 `a.` 会被整体识别为标识符
+                    if start < token.text.len() {
+                        significant.push(sub_token(&token, Token::Ident, start..token.text.len()));
+                    }
+                }
+                // require 在语法上只是普通的名字
+                Token::Require => significant.push(TokenWithText {
+                    kind: Token::Ident,
+                    ..token
+                }),
+                _ => significant.push(token),
+            }
+        }
+        // 同理 `::label::` 的结尾可能被拆成两个冒号，需要合并回来
+        significant.dedup_by(|next, prev| {
+            let adjacent_colons = prev.kind == Token::Operator
+                && prev.text == ":"
+                && next.text == ":"
+                && prev.span.end == next.span.start;
+            if adjacent_colons {
+                prev.text = "::".to_string();
+                prev.span.end = next.span.end;
+            }
+            adjacent_colons
+        });
+        Parser {
+            tokens: significant,
+            pos: 0,
+            eof,
+            dialect,
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    fn text(&self) -> &str {
+        self.tokens
+            .get(self.pos)
+            .map_or("<eof>", |t| t.text.as_str())
+    }
+
+    fn kind(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|t| &t.kind)
+    }
+
+    fn text_at(&self, offset: usize) -> &str {
+        self.tokens
+            .get(self.pos + offset)
+            .map_or("<eof>", |t| t.text.as_str())
+    }
+
+    /// 当前 token 是否是给定的关键字或符号。字符串带引号，不会与之混淆
+    fn at(&self, text: &str) -> bool {
+        self.tokens
+            .get(self.pos)
+            .is_some_and(|t| t.text == text && t.kind != Token::String)
+    }
+
+    fn at_name(&self) -> bool {
+        self.tokens
+            .get(self.pos)
+            .is_some_and(|t| t.kind == Token::Ident && !self.is_reserved(&t.text))
+    }
+
+    fn is_reserved(&self, word: &str) -> bool {
+        // goto 在 Lua 5.1 与 Luau 中只是普通的名字
+        if word == "goto" && matches!(self.dialect, Dialect::Lua51 | Dialect::Luau) {
+            return false;
+        }
+        RESERVED_WORDS.contains(&word)
+    }
+
+    fn advance(&mut self) {
+        self.pos += 1;
+    }
+
+    fn error_near(&self, message: &str) -> SyntaxError {
+        let offset = self.tokens.get(self.pos).map_or(self.eof, |t| t.span.start);
+        SyntaxError {
+            offset,
+            message: format!("{} near '{}'", message, self.text()),
+        }
+    }
+
+    fn unsupported(&self, feature: &str) -> SyntaxError {
+        self.error_near(&format!(
+            "{} is not supported in dialect {}",
+            feature,
+            self.dialect.name()
+        ))
+    }
+
+    fn expect(&mut self, text: &str) -> ParseResult {
+        if !self.at(text) {
+            return Err(self.error_near(&format!("'{}' expected", text)));
+        }
+        self.advance();
+        Ok(())
+    }
+
+    fn expect_name(&mut self) -> ParseResult {
+        if !self.at_name() {
+            return Err(self.error_near("<name> expected"));
+        }
+        self.advance();
+        Ok(())
+    }
+
+    fn block_follow(&self) -> bool {
+        self.finished() || matches!(self.text(), "end" | "else" | "elseif" | "until")
+    }
+
+    fn block(&mut self) -> ParseResult {
+        while !self.block_follow() {
+            if self.at("return") {
+                self.advance();
+                if !self.block_follow() && !self.at(";") {
+                    self.expr_list()?;
+                }
+                if self.at(";") {
+                    self.advance();
+                }
+                if !self.block_follow() {
+                    return Err(self.error_near("'end' expected"));
+                }
+                return Ok(());
+            }
+            self.statement()?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> ParseResult {
+        match self.text() {
+            ";" => self.advance(),
+            "if" => {
+                self.advance();
+                self.expr()?;
+                self.expect("then")?;
+                self.block()?;
+                while self.at("elseif") {
+                    self.advance();
+                    self.expr()?;
+                    self.expect("then")?;
+                    self.block()?;
+                }
+                if self.at("else") {
+                    self.advance();
+                    self.block()?;
+                }
+                self.expect("end")?;
+            }
+            "while" => {
+                self.advance();
+                self.expr()?;
+                self.expect("do")?;
+                self.block()?;
+                self.expect("end")?;
+            }
+            "do" => {
+                self.advance();
+                self.block()?;
+                self.expect("end")?;
+            }
+            "for" => self.for_statement()?,
+            "repeat" => {
+                self.advance();
+                self.block()?;
+                self.expect("until")?;
+                self.expr()?;
+            }
+            "function" => {
+                self.advance();
+                self.expect_name()?;
+                while self.at(".") {
+                    self.advance();
+                    self.expect_name()?;
+                }
+                if self.at(":") {
+                    self.advance();
+                    self.expect_name()?;
+                }
+                self.function_body()?;
+            }
+            "local" => self.local_statement()?,
+            "break" => self.advance(),
+            "goto" if self.is_reserved("goto") => {
+                self.advance();
+                self.expect_name()?;
+            }
+            "::" => {
+                if matches!(self.dialect, Dialect::Lua51 | Dialect::Luau) {
+                    return Err(self.unsupported("label"));
+                }
+                self.advance();
+                self.expect_name()?;
+                self.expect("::")?;
+            }
+            _ if self.kind() == Some(&Token::ContinueKw) => self.advance(),
+            "@" if self.dialect == Dialect::Luau => {
+                // Luau 的函数属性 @native 等
+                self.advance();
+                self.expect_name()?;
+            }
+            "type" | "export" if self.at_type_statement() => self.type_statement()?,
+            _ => self.expr_statement()?,
+        }
+        Ok(())
+    }
+
+    fn for_statement(&mut self) -> ParseResult {
+        self.advance(); // for
+        self.binding()?;
+        if self.at("=") {
+            self.advance();
+            self.expr()?;
+            self.expect(",")?;
+            self.expr()?;
+            if self.at(",") {
+                self.advance();
+                self.expr()?;
+            }
+        } else {
+            while self.at(",") {
+                self.advance();
+                self.binding()?;
+            }
+            self.expect("in")?;
+            self.expr_list()?;
+        }
+        self.expect("do")?;
+        self.block()?;
+        self.expect("end")
+    }
+
+    fn local_statement(&mut self) -> ParseResult {
+        self.advance(); // local
+        if self.at("function") {
+            self.advance();
+            self.expect_name()?;
+            return self.function_body();
+        }
+        loop {
+            self.binding()?;
+            if self.at("<") {
+                if self.dialect != Dialect::Lua54 {
+                    return Err(self.unsupported("local attribute"));
+                }
+                self.advance();
+                self.expect_name()?;
+                self.expect(">")?;
+            }
+            if !self.at(",") {
+                break;
+            }
+            self.advance();
+        }
+        if self.at("=") {
+            self.advance();
+            self.expr_list()?;
+        }
+        Ok(())
+    }
+
+    /// 变量声明中的名字，Luau 中可以带类型注解
+    fn binding(&mut self) -> ParseResult {
+        self.expect_name()?;
+        self.type_annotation()
+    }
+
+    fn type_annotation(&mut self) -> ParseResult {
+        if self.dialect == Dialect::Luau && self.at(":") {
+            self.advance();
+            self.type_expr()?;
+        }
+        Ok(())
+    }
+
+    fn expr_statement(&mut self) -> ParseResult {
+        let kind = self.suffixed_expr()?;
+        if self.at("=") || self.at(",") {
+            if kind != SuffixedKind::Assignable {
+                return Err(self.error_near("syntax error"));
+            }
+            while self.at(",") {
+                self.advance();
+                if self.suffixed_expr()? != SuffixedKind::Assignable {
+                    return Err(self.error_near("syntax error"));
+                }
+            }
+            self.expect("=")?;
+            return self.expr_list();
+        }
+        if self.kind() == Some(&Token::CompoundAssign) {
+            if self.dialect != Dialect::Luau {
+                return Err(self.unsupported("compound assignment"));
+            }
+            if kind != SuffixedKind::Assignable {
+                return Err(self.error_near("syntax error"));
+            }
+            self.advance();
+            return self.expr();
+        }
+        if kind != SuffixedKind::Call {
+            return Err(self.error_near("syntax error"));
+        }
+        Ok(())
+    }
+
+    fn function_body(&mut self) -> ParseResult {
+        if self.dialect == Dialect::Luau && self.at("<") {
+            self.angle_group()?;
+        }
+        self.expect("(")?;
+        if !self.at(")") {
+            loop {
+                if self.at("...") {
+                    self.advance();
+                    self.type_annotation()?;
+                    break;
+                }
+                self.binding()?;
+                if !self.at(",") {
+                    break;
+                }
+                self.advance();
+            }
+        }
+        self.expect(")")?;
+        self.type_annotation()?;
+        self.block()?;
+        self.expect("end")
+    }
+
+    fn expr_list(&mut self) -> ParseResult {
+        self.expr()?;
+        while self.at(",") {
+            self.advance();
+            self.expr()?;
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self) -> ParseResult {
+        self.operand()?;
+        while self.at_binary_operator()? {
+            self.advance();
+            self.operand()?;
+        }
+        Ok(())
+    }
+
+    fn at_binary_operator(&self) -> ParseResult<bool> {
+        let Some(kind) = self.kind() else {
+            return Ok(false);
+        };
+        if !matches!(
+            kind,
+            Token::Operator | Token::Eq | Token::AndKw | Token::OrKw
+        ) {
+            return Ok(false);
+        }
+        match self.text() {
+            "+" | "-" | "*" | "/" | "%" | "^" | ".." | "==" | "~=" | "<" | "<=" | ">" | ">="
+            | "and" | "or" => Ok(true),
+            "//" if matches!(self.dialect, Dialect::Lua54 | Dialect::Luau) => Ok(true),
+            "&" | "|" | "~" | "<<" | ">>" if self.dialect == Dialect::Lua54 => Ok(true),
+            "//" => Err(self.unsupported("operator '//'")),
+            "&" | "|" | "~" | "<<" | ">>" => Err(self.unsupported("bitwise operator")),
+            _ => Ok(false),
+        }
+    }
+
+    /// 一元运算符与简单表达式
+    fn operand(&mut self) -> ParseResult {
+        while matches!(self.text(), "not" | "-" | "#" | "~") && self.kind() != Some(&Token::String)
+        {
+            if self.at("~") && self.dialect != Dialect::Lua54 {
+                return Err(self.unsupported("bitwise operator"));
+            }
+            self.advance();
+        }
+        self.simple_expr()?;
+        while self.dialect == Dialect::Luau && self.at("::") {
+            self.advance();
+            self.type_expr()?;
+        }
+        Ok(())
+    }
+
+    fn simple_expr(&mut self) -> ParseResult {
+        match self.kind() {
+            Some(Token::Number) => {
+                self.check_number()?;
+                self.advance();
+            }
+            Some(Token::String) => self.advance(),
+            Some(Token::InterpString) => self.interpolated_string()?,
+            Some(Token::LBrace) => self.table()?,
+            Some(Token::FunctionKw) => {
+                self.advance();
+                self.function_body()?;
+            }
+            Some(Token::IfKw) if self.dialect == Dialect::Luau => self.if_expr()?,
+            _ => match self.text() {
+                "nil" | "true" | "false" | "..." => self.advance(),
+                _ => {
+                    self.suffixed_expr()?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn check_number(&self) -> ParseResult {
+        let text = self.text().to_ascii_lowercase();
+        let is_hex = text.starts_with("0x");
+        let has_suffix = text.ends_with("ll") || (text.ends_with('i') && !is_hex);
+        if has_suffix && self.dialect != Dialect::LuaJit {
+            return Err(self.unsupported("numeric suffix"));
+        }
+        let luau_literal = text.starts_with("0b") || text.contains('_');
+        if luau_literal && self.dialect != Dialect::Luau {
+            return Err(self.unsupported("number literal"));
+        }
+        Ok(())
+    }
+
+    fn suffixed_expr(&mut self) -> ParseResult<SuffixedKind> {
+        let mut kind = if self.at("(") {
+            self.advance();
+            self.expr()?;
+            self.expect(")")?;
+            SuffixedKind::Other
+        } else if self.at_name() {
+            self.advance();
+            SuffixedKind::Assignable
+        } else {
+            return Err(self.error_near("unexpected symbol"));
+        };
+        loop {
+            match self.kind() {
+                Some(Token::Operator) if self.at(".") => {
+                    self.advance();
+                    self.expect_name()?;
+                    kind = SuffixedKind::Assignable;
+                }
+                Some(Token::Operator) if self.at(":") => {
+                    self.advance();
+                    self.expect_name()?;
+                    self.call_args()?;
+                    kind = SuffixedKind::Call;
+                }
+                Some(Token::LBracket) => {
+                    self.advance();
+                    self.expr()?;
+                    self.expect("]")?;
+                    kind = SuffixedKind::Assignable;
+                }
+                Some(Token::LParen | Token::LBrace | Token::String) => {
+                    self.call_args()?;
+                    kind = SuffixedKind::Call;
+                }
+                _ => return Ok(kind),
+            }
+        }
+    }
+
+    fn call_args(&mut self) -> ParseResult {
+        match self.kind() {
+            Some(Token::String) => self.advance(),
+            Some(Token::LBrace) => self.table()?,
+            _ => {
+                self.expect("(")?;
+                if !self.at(")") {
+                    self.expr_list()?;
+                }
+                self.expect(")")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn table(&mut self) -> ParseResult {
+        self.expect("{")?;
+        while !self.at("}") {
+            if self.at("[") {
+                self.advance();
+                self.expr()?;
+                self.expect("]")?;
+                self.expect("=")?;
+                self.expr()?;
+            } else if self.at_name() && self.text_at(1) == "=" {
+                self.advance();
+                self.advance();
+                self.expr()?;
+            } else {
+                self.expr()?;
+            }
+            if self.at(",") || self.at(";") {
+                self.advance();
+            } else {
+                break;
+            }
+        }
+        self.expect("}")
+    }
+
+    fn if_expr(&mut self) -> ParseResult {
+        self.advance(); // if
+        self.expr()?;
+        self.expect("then")?;
+        self.expr()?;
+        while self.at("elseif") {
+            self.advance();
+            self.expr()?;
+            self.expect("then")?;
+            self.expr()?;
+        }
+        self.expect("else")?;
+        self.expr()
+    }
+
+    /// 插值字符串：逐个检查 {} 中的表达式
+    fn interpolated_string(&mut self) -> ParseResult {
+        if self.dialect != Dialect::Luau {
+            return Err(self.unsupported("string interpolation"));
+        }
+        let token = self.tokens[self.pos].clone();
+        if token.text.len() < 2 || !token.text.ends_with('`') {
+            return Err(self.error_near("unfinished string"));
+        }
+        for (is_expression, range) in split_interpolation(&token.text) {
+            if !is_expression {
+                continue;
+            }
+            let hole = &token.text[range.clone()];
+            let offset = token.span.start + range.start;
+            let mut parser = Parser::new(tokenize(hole, self.dialect), hole.len(), self.dialect);
+            parser
+                .expr()
+                .and_then(|_| {
+                    if parser.finished() {
+                        Ok(())
+                    } else {
+                        Err(parser.error_near("'}' expected"))
+                    }
+                })
+                .map_err(|err| SyntaxError {
+                    offset: err.offset + offset,
+                    message: err.message,
+                })?;
+        }
+        self.advance();
+        Ok(())
+    }
+
+    fn at_type_statement(&self) -> bool {
+        if self.dialect != Dialect::Luau {
+            return false;
+        }
+        if self.at("export") {
+            self.text_at(1) == "type"
+        } else {
+            self.tokens
+                .get(self.pos + 1)
+                .is_some_and(|t| t.kind == Token::Ident)
+        }
+    }
+
+    fn type_statement(&mut self) -> ParseResult {
+        if self.at("export") {
+            self.advance();
+        }
+        self.advance(); // type
+        self.expect_name()?;
+        if self.at("<") {
+            self.angle_group()?;
+        }
+        self.expect("=")?;
+        self.type_expr()
+    }
+
+    fn type_expr(&mut self) -> ParseResult {
+        if self.at("|") || self.at("&") {
+            self.advance();
+        }
+        self.simple_type()?;
+        loop {
+            if self.at("?") {
+                self.advance();
+            } else if self.at("|") || self.at("&") {
+                self.advance();
+                self.simple_type()?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn simple_type(&mut self) -> ParseResult {
+        match self.kind() {
+            Some(Token::String) => self.advance(),
+            Some(Token::LBrace) => self.table_type()?,
+            Some(Token::LParen) => {
+                self.type_pack()?;
+                if self.at_arrow() {
+                    self.advance();
+                    self.advance();
+                    self.type_expr()?;
+                }
+            }
+            _ if self.at("<") => {
+                // 泛型函数类型 <T>(T) -> T
+                self.angle_group()?;
+                self.type_pack()?;
+                if !self.at_arrow() {
+                    return Err(self.error_near("'->' expected"));
+                }
+                self.advance();
+                self.advance();
+                self.type_expr()?;
+            }
+            _ if self.at("...") => {
+                self.advance();
+                self.type_expr()?;
+            }
+            _ if self.at("typeof") && self.text_at(1) == "(" => {
+                self.advance();
+                self.advance();
+                self.expr()?;
+                self.expect(")")?;
+            }
+            _ if self.at("nil") || self.at("true") || self.at("false") || self.at_name() => {
+                self.advance();
+                if self.at(".") {
+                    self.advance();
+                    self.expect_name()?;
+                }
+                if self.at("<") {
+                    self.angle_group()?;
+                }
+                if self.at("...") {
+                    // 泛型类型包 T...
+                    self.advance();
+                }
+            }
+            _ => return Err(self.error_near("type expected")),
+        }
+        Ok(())
+    }
+
+    fn at_arrow(&self) -> bool {
+        self.at("-") && self.text_at(1) == ">"
+    }
+
+    /// 括号中的类型列表，元素可以带参数名 `name: type`
+    fn type_pack(&mut self) -> ParseResult {
+        self.expect("(")?;
+        while !self.at(")") {
+            if self.at_name() && self.text_at(1) == ":" {
+                self.advance();
+                self.advance();
+            }
+            self.type_expr()?;
+            if !self.at(",") {
+                break;
+            }
+            self.advance();
+        }
+        self.expect(")")
+    }
+
+    fn table_type(&mut self) -> ParseResult {
+        self.expect("{")?;
+        // 数组类型 { T }
+        let at_property = self.at("[")
+            || (self.at_name() && self.text_at(1) == ":")
+            || (matches!(self.text(), "read" | "write") && self.text_at(2) == ":");
+        if !self.at("}") && !at_property {
+            self.type_expr()?;
+            return self.expect("}");
+        }
+        while !self.at("}") {
+            if self.at("[") {
+                self.advance();
+                self.type_expr()?;
+                self.expect("]")?;
+            } else {
+                if matches!(self.text(), "read" | "write") && self.text_at(1) != ":" {
+                    self.advance();
+                }
+                self.expect_name()?;
+            }
+            self.expect(":")?;
+            self.type_expr()?;
+            if self.at(",") || self.at(";") {
+                self.advance();
+            } else {
+                break;
+            }
+        }
+        self.expect("}")
+    }
+
+    /// 泛型参数或实参列表 <...>，只检查括号配对。>> 与 >= 会被词法分析成一个运算符
+    fn angle_group(&mut self) -> ParseResult {
+        let mut depth = 0usize;
+        loop {
+            if self.finished() {
+                return Err(self.error_near("'>' expected"));
+            }
+            let text = self.text();
+            depth += text.matches('<').count();
+            let closing = text.matches('>').count();
+            if closing > depth {
+                return Err(self.error_near("unexpected symbol"));
+            }
+            depth -= closing;
+            self.advance();
+            if depth == 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn sub_token(token: &TokenWithText, kind: Token, range: std::ops::Range<usize>) -> TokenWithText {
+    TokenWithText {
+        kind,
+        text: token.text[range.clone()].to_string(),
+        span: token.span.start + range.start..token.span.start + range.end,
+    }
+}
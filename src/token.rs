@@ -1,4 +1,38 @@
-use logos::Logos;
+use logos::{Lexer, Logos};
+use std::ops::Range;
+
+/// 源码所使用的 Lua 方言。词法规则取各方言的并集，方言只影响少量需要上下文的语法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dialect {
+    Lua51,
+    LuaJit,
+    #[default]
+    Lua54,
+    Luau,
+}
+
+impl Dialect {
+    /// 从 dlua.json 中的写法解析方言名
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "lua51" => Some(Dialect::Lua51),
+            "luajit" => Some(Dialect::LuaJit),
+            "lua54" => Some(Dialect::Lua54),
+            "luau" => Some(Dialect::Luau),
+            _ => None,
+        }
+    }
+
+    /// dlua.json 中的写法，用于提示信息
+    pub fn name(self) -> &'static str {
+        match self {
+            Dialect::Lua51 => "lua51",
+            Dialect::LuaJit => "luajit",
+            Dialect::Lua54 => "lua54",
+            Dialect::Luau => "luau",
+        }
+    }
+}
 
 #[derive(Logos, Debug, PartialEq, Clone)]
 pub enum Token {
@@ -7,13 +41,18 @@ pub enum Token {
     MacroComment,
     #[regex(r"--\s*@alias[^\n]*", priority = 40)]
     AliasComment,
+    // 只匹配 @use 本身或后面跟着空白的情况，`-- @user` 这样的注释仍是普通注释
+    #[regex(r"--\s*@use([ \t][^\n]*)?", priority = 40)]
+    UseComment,
     #[regex(r"--\s*@if[^\n]*", priority = 39)]
     IfComment,
     #[regex(r"--\s*@endif[^\n]*", priority = 38)]
     EndIfComment,
 
-    // 普通注释
-    #[regex(r"--[^\n]*", priority = 20)]
+    // 普通注释。单行注释不能以长括号开头，否则是跨行的长注释 --[[ ... ]]
+    #[regex(r"--([^\[\n][^\n]*)?", priority = 20)]
+    #[regex(r"--\[=*([^\[=\n][^\n]*)?", priority = 20)]
+    #[regex(r"--\[=*\[", long_bracket, priority = 20)]
     Comment,
 
     // 结构关键词（用于作用域划分）
@@ -45,6 +84,14 @@ pub enum Token {
     ReturnKw,
     #[token("in", priority = 30)]
     InKw,
+    // Luau 的 continue 是上下文关键字，由 tokenize 从 Ident 转换而来
+    ContinueKw,
+    #[token("and", priority = 30)]
+    AndKw,
+    #[token("or", priority = 30)]
+    OrKw,
+    #[token("not", priority = 30)]
+    NotKw,
     // require
     #[token("require", priority = 25)]
     Require,
@@ -56,19 +103,57 @@ pub enum Token {
     RParen,
     #[token(",", priority = 10)]
     Comma,
+    #[token("[", priority = 10)]
+    LBracket,
+    #[token("]", priority = 10)]
+    RBracket,
+    #[token("{", priority = 10)]
+    LBrace,
+    #[token("}", priority = 10)]
+    RBrace,
+    #[token(";", priority = 10)]
+    Semicolon,
 
-    // 广义标识符，允许点号和冒号的出现
-    #[regex(r"[A-Za-z_][A-Za-z0-9_\.:]*", priority = 5)]
+    // 广义标识符，允许由点号和冒号连接的索引链，如 a.b.c、obj:method
+    #[regex(r"[A-Za-z_][A-Za-z0-9_]*([\.:][A-Za-z_][A-Za-z0-9_]*)*", priority = 5)]
     Ident,
 
     // 字符串（如果仍需 require 分析）
     #[regex(r#""([^"\\]|\\(\r\n|\n|.))*""#, priority = 3)]
     #[regex(r#"'([^'\\]|\\(\r\n|\n|.))*'"#, priority = 3)]
+    #[regex(r"\[=*\[", long_bracket, priority = 3)]
     String,
+    // Luau 的插值字符串 `text {expr} text`
+    #[token("`", interpolated_string, priority = 3)]
+    InterpString,
+
+    // 数字（十进制与十六进制，含小数与指数）
+    // 另含 LuaJIT 的 LL/ULL/i 后缀，以及 Luau 的二进制字面量与数字分隔符 _
+    #[regex(
+        r"[0-9][0-9_]*(\.[0-9_]*)?([eE][+-]?[0-9]+)?([iI]|[uU]?[lL][lL])?",
+        priority = 6
+    )]
+    #[regex(r"\.[0-9][0-9_]*([eE][+-]?[0-9]+)?[iI]?", priority = 6)]
+    #[regex(
+        r"0[xX][0-9a-fA-F_]*(\.[0-9a-fA-F]*)?([pP][+-]?[0-9]+)?([iI]|[uU]?[lL][lL])?",
+        priority = 6
+    )]
+    #[regex(r"0[bB][01_]+", priority = 6)]
+    Number,
 
     // 赋值符号
     #[token("=", priority = 4)]
     Assign,
+    // 相等比较，需与赋值区分，否则 `v == 1` 会被当作对 v 的赋值
+    #[token("==", priority = 4)]
+    Eq,
+    // Luau 的复合赋值
+    #[regex(r"\+=|-=|\*=|/=|//=|%=|\^=|\.\.=", priority = 4)]
+    CompoundAssign,
+
+    // 其余运算符，用于判断表达式的边界
+    #[regex(r"~=|<=|>=|\.\.\.?|//|<<|>>|::|[-+*/%^#&~|<>.:]", priority = 4)]
+    Operator,
 
     // 空白
     #[regex(r"[ \t\r\n]+", priority = 2)]
@@ -77,4 +162,213 @@ pub enum Token {
     // 其它（原样透传）
     #[regex(r".", priority = 0)]
     Other,
-}
\ No newline at end of file
+}
+
+/// 长括号 [[ ... ]] / [==[ ... ]==]：吞掉直到对应等级的闭括号，未闭合则吞到文件末尾
+fn long_bracket(lex: &mut Lexer<Token>) {
+    let level = lex.slice().bytes().filter(|&b| b == b'=').count();
+    let close = format!("]{}]", "=".repeat(level));
+    let len = match lex.remainder().find(&close) {
+        Some(idx) => idx + close.len(),
+        None => lex.remainder().len(),
+    };
+    lex.bump(len);
+}
+
+/// Luau 插值字符串：吞掉直到不在 {} 内、未被转义的反引号，未闭合则吞到文件末尾
+fn interpolated_string(lex: &mut Lexer<Token>) {
+    let len = match interpolation_end(lex.remainder()) {
+        Some(idx) => idx + 1,
+        None => lex.remainder().len(),
+    };
+    lex.bump(len);
+}
+
+/// 在开头反引号之后的文本中找到闭合反引号的下标
+fn interpolation_end(rest: &str) -> Option<usize> {
+    let bytes = rest.as_bytes();
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'{' => depth += 1,
+            b'}' if depth > 0 => depth -= 1,
+            b'`' if depth == 0 => return Some(i),
+            quote @ (b'"' | b'\'') if depth > 0 => {
+                // 插值表达式中的普通字符串
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote {
+                    if bytes[i] == b'\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// 把插值字符串（含两端反引号）切分为原样文本与 {} 中的表达式，返回 (是否为表达式, 字节区间)
+pub fn split_interpolation(text: &str) -> Vec<(bool, Range<usize>)> {
+    let bytes = text.as_bytes();
+    let mut parts = Vec::new();
+    let mut literal_start = 0;
+    let mut i = 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'{' => {
+                parts.push((false, literal_start..i + 1));
+                let hole_start = i + 1;
+                let hole_end = matching_brace(&text[hole_start..])
+                    .map(|idx| hole_start + idx)
+                    .unwrap_or(text.len());
+                parts.push((true, hole_start..hole_end));
+                literal_start = hole_end;
+                i = hole_end;
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    if literal_start < text.len() {
+        parts.push((false, literal_start..text.len()));
+    }
+    parts
+}
+
+/// 在插值表达式的文本中找到闭合它的 } 的下标
+fn matching_brace(rest: &str) -> Option<usize> {
+    let bytes = rest.as_bytes();
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' => depth += 1,
+            b'}' if depth == 0 => return Some(i),
+            b'}' => depth -= 1,
+            quote @ (b'"' | b'\'') => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote {
+                    if bytes[i] == b'\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+#[derive(Debug, Clone)]
+pub struct TokenWithText {
+    pub kind: Token,
+    pub text: String,
+    // 字节区间，用于定位
+    pub span: Range<usize>,
+}
+
+/// 对源码分词。词法规则取各方言的并集，这里再按方言做需要上下文的后处理：
+/// Luau 中把 a:b 拆开（否则 `x:number` 会被当作方法名），并识别作为语句的 continue
+pub fn tokenize(src: &str, dialect: Dialect) -> Vec<TokenWithText> {
+    let mut lexer = Token::lexer(src);
+    let mut tokens = Vec::new();
+    while let Some(token_result) = lexer.next() {
+        // 忽略单个 token 错误
+        if let Ok(kind) = token_result {
+            let span = lexer.span();
+            if dialect == Dialect::Luau && kind == Token::Ident && span_contains_colon(src, &span) {
+                split_colons(src, span, &mut tokens);
+                continue;
+            }
+            tokens.push(TokenWithText {
+                kind,
+                text: src[span.clone()].to_string(),
+                span,
+            });
+        }
+    }
+    if dialect == Dialect::Luau {
+        mark_continue_keywords(&mut tokens);
+    }
+    tokens
+}
+
+fn span_contains_colon(src: &str, span: &Range<usize>) -> bool {
+    src[span.clone()].contains(':')
+}
+
+/// 把 a.b:c 拆成 a.b、:、c 三个 token
+fn split_colons(src: &str, span: Range<usize>, tokens: &mut Vec<TokenWithText>) {
+    let mut start = span.start;
+    for (idx, ch) in src[span.clone()].char_indices() {
+        if ch != ':' {
+            continue;
+        }
+        let colon = span.start + idx;
+        tokens.push(TokenWithText {
+            kind: Token::Ident,
+            text: src[start..colon].to_string(),
+            span: start..colon,
+        });
+        tokens.push(TokenWithText {
+            kind: Token::Operator,
+            text: ":".to_string(),
+            span: colon..colon + 1,
+        });
+        start = colon + 1;
+    }
+    // 词法分析不回溯，`name: type` 中的 `name:` 会被整体识别为标识符，冒号之后可能为空
+    if start < span.end {
+        tokens.push(TokenWithText {
+            kind: Token::Ident,
+            text: src[start..span.end].to_string(),
+            span: start..span.end,
+        });
+    }
+}
+
+/// continue 后面不是赋值、调用、索引等能让它成为普通名字的 token 时，视为 continue 语句
+fn mark_continue_keywords(tokens: &mut [TokenWithText]) {
+    for i in 0..tokens.len() {
+        if tokens[i].kind != Token::Ident || tokens[i].text != "continue" {
+            continue;
+        }
+        let next = tokens[i + 1..]
+            .iter()
+            .find(|t| !matches!(t.kind, Token::Whitespace | Token::Comment));
+        let prev = tokens[..i]
+            .iter()
+            .rev()
+            .find(|t| !matches!(t.kind, Token::Whitespace | Token::Comment));
+        let used_as_name = matches!(
+            next.map(|t| &t.kind),
+            Some(
+                Token::Assign
+                    | Token::CompoundAssign
+                    | Token::Comma
+                    | Token::LParen
+                    | Token::LBracket
+                    | Token::LBrace
+                    | Token::String
+                    | Token::InterpString
+            )
+        ) || next
+            .is_some_and(|t| t.kind == Token::Operator && matches!(t.text.as_str(), "." | ":"))
+            || matches!(
+                prev.map(|t| &t.kind),
+                Some(Token::LocalKw | Token::FunctionKw | Token::Operator)
+            );
+        if !used_as_name {
+            tokens[i].kind = Token::ContinueKw;
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use regex::{Captures, Regex};
+use std::{
+    collections::HashMap,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    code::Code,
+    error::{Error, Result},
+    source_map::SourceMap,
+};
+
+/// `dlua trace <export_path>`：从标准输入读取 Lua 的报错信息或 traceback，
+/// 返回把其中指向输出文件的 `file.lua:line` 改写为源码位置之后的文本
+pub fn run(export_path: &Path) -> Result<String> {
+    let mut input = String::new();
+    io::stdin().read_to_string(&mut input).map_err(|err| {
+        Error::new(
+            Code::Io,
+            format!("failed to read traceback from stdin: {}", err),
+        )
+    })?;
+    Ok(rewrite(&input, export_path))
+}
+
+/// 改写文本中所有能找到源码映射的 `file.lua:line` 与 `file.lua:line:column`，找不到的保持原样
+pub fn rewrite(text: &str, export_path: &Path) -> String {
+    let location = Regex::new(r#"([^\s:"'\[\]()<>]+\.lua):(\d+)(?::(\d+))?"#).unwrap();
+    let mut maps: HashMap<String, Option<SourceMap>> = HashMap::new();
+    location
+        .replace_all(text, |caps: &Captures| {
+            let path = &caps[1];
+            let Ok(line) = caps[2].parse::<usize>() else {
+                return caps[0].to_string();
+            };
+            let column = caps.get(3).and_then(|c| c.as_str().parse::<usize>().ok());
+            let source_map = maps
+                .entry(path.to_string())
+                .or_insert_with(|| load_source_map(path, export_path));
+            let Some(source_map) = source_map.as_ref() else {
+                return caps[0].to_string();
+            };
+            let Some(found) = source_map.lookup(line, column) else {
+                return caps[0].to_string();
+            };
+            let mut rewritten = format!("{}:{}", source_map.source, found.source_line);
+            if let Some(source_column) = found.source_column {
+                rewritten.push_str(&format!(":{}", source_column));
+            }
+            if let Some(name) = found.macro_name {
+                rewritten.push_str(&format!(" (in expansion of macro {})", name));
+            } else if !found.expansions.is_empty() {
+                let expansions: Vec<String> = found
+                    .expansions
+                    .iter()
+                    .map(|(name, column)| format!("{} at column {}", name, column))
+                    .collect();
+                let what = if expansions.len() == 1 {
+                    "an expansion of macro"
+                } else {
+                    "expansions of macros"
+                };
+                rewritten.push_str(&format!(
+                    " (line contains {} {})",
+                    what,
+                    expansions.join(", ")
+                ));
+            }
+            rewritten
+        })
+        .into_owned()
+}
+
+/// 找到 traceback 中的路径所对应的输出文件的源码映射。
+/// 游戏中的路径可能带有额外的前缀目录，因此依次去掉开头的目录再在 export_path 下查找
+fn load_source_map(path: &str, export_path: &Path) -> Option<SourceMap> {
+    let path = Path::new(path);
+    let mut candidates: Vec<PathBuf> = Vec::new();
+    if let Ok(relative) = path.strip_prefix(export_path) {
+        candidates.push(export_path.join(relative));
+    }
+    let components: Vec<_> = path.components().collect();
+    for start in 0..components.len() {
+        let rest: PathBuf = components[start..].iter().collect();
+        if rest.is_absolute() {
+            continue;
+        }
+        candidates.push(export_path.join(rest));
+    }
+    candidates.into_iter().find_map(|candidate| {
+        let data = std::fs::read_to_string(SourceMap::path_for(&candidate)).ok()?;
+        serde_json::from_str(&data).ok()
+    })
+}
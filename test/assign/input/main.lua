@@ -0,0 +1,27 @@
+-- @macro
+v = 1
+-- @macro
+W = 2
+
+local t = { v = 10, w = { v = 20 } }
+t.v = v
+t.w.v = W
+assert(t.v == 1 and v == 1)
+
+function t.v()
+    return v
+end
+assert(t.v() == 1)
+
+local function f()
+    local v = 5
+    v = v + 1
+    return v
+end
+assert(f() == 6 and v == 1)
+
+t[v], t[W] = W, v
+assert(t[1] == 2 and v == 1)
+
+W, x = W + 1, W
+assert(W == 3 and x == 2)
@@ -0,0 +1,25 @@
+
+
+
+local t = { v = 10, w = { v = 20 } }
+t.v = 1
+t.w.v = 2
+assert(t.v == 1 and 1 == 1)
+
+function t.v()
+    return 1
+end
+assert(t.v() == 1)
+
+local function f()
+    local v = 5
+    v = v + 1
+    return v
+end
+assert(f() == 6 and 1 == 1)
+
+t[1], t[2] = 2, 1
+assert(t[1] == 2 and 1 == 1)
+
+W, x = 2 + 1, 2
+assert(W == 3 and x == 2)
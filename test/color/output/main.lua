@@ -0,0 +1,3 @@
+
+local m = require("missing")
+print(10)
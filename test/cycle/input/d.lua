@@ -0,0 +1,2 @@
+local e = require("e")
+return {}
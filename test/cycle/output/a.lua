@@ -0,0 +1,3 @@
+local b = require("b")
+local c = require("c")
+return {}
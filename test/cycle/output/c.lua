@@ -0,0 +1,3 @@
+local a = require("a")
+local b = require("b")
+return {}
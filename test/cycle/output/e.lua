@@ -0,0 +1,2 @@
+local d = require("d")
+return {}
@@ -0,0 +1,3 @@
+-- @macro
+MP = 5
+return {}
@@ -0,0 +1,3 @@
+-- @macro
+HP = 100
+return {}
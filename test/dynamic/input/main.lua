@@ -0,0 +1,6 @@
+local name = "forest"
+local l = require("levels." .. name)
+print(HP)
+-- @requires "levels.cave"
+local c = require(name)
+print(MP)
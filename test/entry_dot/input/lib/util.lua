@@ -0,0 +1,6 @@
+-- @macro
+function TWICE(x)
+	x * 2
+end
+local util = {}
+return util
@@ -0,0 +1,4 @@
+require("macros")
+local util = require("lib.util")
+local speed = SPEED
+print(CLAMP(speed, 0, 10), TWICE(speed))
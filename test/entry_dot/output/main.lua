@@ -0,0 +1,4 @@
+require("macros")
+local util = require("lib.util")
+local speed = 16
+print(math.max(0, math.min(10, speed)), speed * 2)
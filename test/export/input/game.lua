@@ -0,0 +1,7 @@
+-- @export
+require("macros")
+-- @macro
+GRAVITY = 9.8
+
+local game = {}
+return game
@@ -0,0 +1,6 @@
+-- @macro
+SPEED = 16
+-- @macro
+function CLAMP(x, lo, hi)
+	return math.max(lo, math.min(hi, x))
+end
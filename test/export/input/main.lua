@@ -0,0 +1,3 @@
+local game = require("game")
+local x = SPEED * GRAVITY
+print(CLAMP(x, 0, 100))
@@ -0,0 +1,6 @@
+-- @export
+require("macros")
+
+
+local game = {}
+return game
@@ -0,0 +1,3 @@
+local game = require("game")
+local x = 16 * 9.8
+print(math.max(0, math.min(100, x)))
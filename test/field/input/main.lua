@@ -0,0 +1,27 @@
+-- @macro
+ITER = 1000
+-- @macro
+CONFIG = settings
+
+local self = { ITER = 1 }
+self.ITER = ITER
+assert(self.ITER == 1000)
+
+local obj = {}
+function obj:ITER()
+    return self.ITER
+end
+assert(obj:ITER() == 1000)
+assert((self).ITER == obj.ITER(obj))
+
+local t = { ITER = ITER, [ITER] = true; ITER = 2 }
+assert(t[ITER] and t.ITER == 2)
+
+print(CONFIG.width, CONFIG:get("height"))
+print(ITER)
+
+-- @macro
+V = 10
+print(V)
+::V:: V = 1
+assert(V == 1)
@@ -0,0 +1,24 @@
+
+
+
+local self = { ITER = 1 }
+self.ITER = 1000
+assert(self.ITER == 1000)
+
+local obj = {}
+function obj:ITER()
+    return self.ITER
+end
+assert(obj:ITER() == 1000)
+assert((self).ITER == obj.ITER(obj))
+
+local t = { ITER = 1000, [1000] = true; ITER = 2 }
+assert(t[1000] and t.ITER == 2)
+
+print(settings.width, settings:get("height"))
+print(1000)
+
+
+print(10)
+::V:: V = 1
+assert(V == 1)
@@ -0,0 +1,8 @@
+print(SPEED)
+local function f()
+  require("macros")
+  return SPEED
+end
+print(SPEED)
+require("macros")
+print(SPEED)
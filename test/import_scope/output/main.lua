@@ -0,0 +1,8 @@
+print(SPEED)
+local function f()
+  require("macros")
+  return 16
+end
+print(SPEED)
+require("macros")
+print(16)
@@ -0,0 +1,7 @@
+
+
+local ffi = require("ffi")
+local big = 123456789LL * 2LL
+local z = 3 + 4i
+local masked = bit.band(big, 0xFFFFFFFFULL)
+print(masked, z, 1ULL, 0x10ll)
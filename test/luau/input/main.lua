@@ -0,0 +1,36 @@
+-- @macro
+SPEED = 16
+-- @macro
+function CLAMP(x, lo, hi)
+	return math.clamp(x, lo, hi)
+end
+
+export type Unit = { SPEED: number, name: string? }
+type Pair<T> = { first: T, second: Map<string, Array<T>> }
+
+local function move(unit: Unit, dt: number): number
+	local SPEED: number = unit.SPEED * dt
+	return SPEED
+end
+
+local speeds: { number } = {}
+for i: number = 1, SPEED do
+	if i % 2 == 0 then
+		continue
+	end
+	speeds[i] = CLAMP(i, 0, SPEED)
+end
+
+local total = 0
+total += SPEED
+total //= 2
+local label = `speed={SPEED} clamped={CLAMP(total, 0, 4)} name={"x"}`
+local kind = if total > SPEED then "fast" elseif total == SPEED then "exact" else "slow"
+local n = (speeds :: any).n
+local obj = { continue = SPEED }
+print(obj:method(SPEED), move({ SPEED = SPEED, name = "u" }, 0.5), label, kind, n)
+
+-- @macro
+V = 1
+local function g() local V = 2 return if true then V else 0 end
+print(V)
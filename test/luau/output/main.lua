@@ -0,0 +1,29 @@
+
+export type Unit = { SPEED: number, name: string? }
+type Pair<T> = { first: T, second: Map<string, Array<T>> }
+
+local function move(unit: Unit, dt: number): number
+	local SPEED: number = unit.SPEED * dt
+	return SPEED
+end
+
+local speeds: { number } = {}
+for i: number = 1, 16 do
+	if i % 2 == 0 then
+		continue
+	end
+	speeds[i] = math.clamp(i, 0, SPEED)
+end
+
+local total = 0
+total += 16
+total //= 2
+local label = `speed={16} clamped={math.clamp(total, 0, 4)} name={"x"}`
+local kind = if total > 16 then "fast" elseif total == 16 then "exact" else "slow"
+local n = (speeds :: any).n
+local obj = { continue = 16 }
+print(obj:method(16), move({ SPEED = 16, name = "u" }, 0.5), label, kind, n)
+
+
+local function g() local V = 2 return if true then V else 0 end
+print(1)
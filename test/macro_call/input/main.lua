@@ -0,0 +1,16 @@
+-- @macro
+function ADD(a, b)
+    return a + b
+end
+-- @macro
+function INC(x)
+    x = x + 1
+end
+
+-- 展开结果紧贴调用位置，调用之后的 token 原样保留
+local x, y = ADD(1, 2), 3
+print(ADD(x, 1))
+local t = { ADD(1, 2), ADD(3, 4) }
+INC(x)
+INC(x) print(x)
+if ADD(x, 1) > 10 then INC(x) end
@@ -0,0 +1,7 @@
+-- 展开结果紧贴调用位置，调用之后的 token 原样保留
+local x, y = 1 + 2, 3
+print(x + 1)
+local t = { 1 + 2, 3 + 4 }
+x = x + 1
+x = x + 1 print(x)
+if x + 1 > 10 then x = x + 1 end
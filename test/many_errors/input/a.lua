@@ -0,0 +1,6 @@
+-- @macro
+function ADD(a, b)
+	return a + b
+end
+local x = ADD(1, 2, 3)
+local y = ADD(1)
@@ -0,0 +1,2 @@
+local m = require("missing")
+local v = require("mod" .. "ule")
@@ -0,0 +1,2 @@
+-- @macro
+LIMIT 100
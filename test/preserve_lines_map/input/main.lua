@@ -0,0 +1,12 @@
+-- @macro
+function ADD(a, b)
+	a + b
+end
+-- @macro
+SPEED = 16
+local x = 1
+-- @alias
+local FAST = SPEED
+local y = ADD(x,
+	2)
+error("boom " .. y .. FAST)
@@ -0,0 +1,12 @@
+
+
+
+
+
+
+local x = 1
+
+
+local y = x + 2
+
+error("boom " .. y .. 16)
@@ -0,0 +1,4 @@
+-- @macro
+LIMIT = 10
+local m = require("missing")
+print(LIMIT)
@@ -0,0 +1,2 @@
+-- @macro
+SPEED = 16
@@ -0,0 +1,9 @@
+require("fast")
+require("slow")
+-- @macro
+function CLAMP(x, lo, hi)
+	return math.max(lo, math.min(hi, x))
+end
+
+local v = CLAMP
+print(SPEED, v)
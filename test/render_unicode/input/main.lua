@@ -0,0 +1,7 @@
+-- @macro
+function CLAMP(x, lo, hi)
+	return math.max(lo, math.min(hi, x))
+end
+
+local v = { name = "速度", CLAMP }
+print(v)
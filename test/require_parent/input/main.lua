@@ -0,0 +1,2 @@
+require("consts")
+print(SIZE)
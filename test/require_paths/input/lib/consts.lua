@@ -0,0 +1,2 @@
+-- @macro
+SIZE = 8
@@ -0,0 +1,4 @@
+-- @macro
+function AREA(w, h)
+	return w * h
+end
@@ -0,0 +1,4 @@
+require("consts")
+require("shapes")
+local util = require("util")
+print(SIZE, AREA(2, 3), util)
@@ -0,0 +1,14 @@
+-- @macro
+C = 42
+
+do
+    --[[
+    local C = 0
+    end
+    ]]
+    local s = [==[
+    local C = 1 end
+    ]==]
+    assert(C == 42)
+end
+assert(C == 42)
@@ -0,0 +1,13 @@
+-- @macro
+K = 5
+
+local sum = 0
+for K = 1, K do
+    sum = sum + K
+end
+assert(sum == 15)
+
+for _, K in ipairs({ K, K }) do
+    sum = sum + K
+end
+assert(K == 5)
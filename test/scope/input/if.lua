@@ -0,0 +1,24 @@
+-- @macro
+X = 1
+
+local r
+if X == 1 then
+    local X = 2
+    r = X
+elseif X == 2 then
+    local X = 3
+    r = X
+else
+    local X = 4
+    r = X
+end
+assert(r == 2)
+
+if X > 1 then
+    r = 0
+elseif X < 1 then
+    r = 0
+elseif X == 1 then
+    r = X
+end
+assert(X == r)
@@ -0,0 +1,15 @@
+-- @macro
+V = 7
+
+do
+    local V = V + 1
+    assert(V == 8)
+end
+
+local a, b = V, V * 2
+assert(a + b == 21)
+
+local function f(V)
+    return V
+end
+assert(f(V) == V)
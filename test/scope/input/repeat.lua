@@ -0,0 +1,14 @@
+-- @macro
+DONE = false
+
+local n = 0
+repeat
+    n = n + 1
+    local DONE = n >= 3
+until DONE
+assert(n == 3)
+
+repeat
+    n = n - 1
+until DONE or n == 0
+assert(DONE == false)
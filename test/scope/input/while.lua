@@ -0,0 +1,14 @@
+-- @macro
+N = 3
+
+local i = 0
+while i < N do
+    local N = 10
+    i = i + N
+end
+assert(i == 10)
+
+while i < N * 10 do
+    i = i + 1
+end
+assert(N == 3)
@@ -0,0 +1,13 @@
+
+
+do
+    --[[
+    local C = 0
+    end
+    ]]
+    local s = [==[
+    local C = 1 end
+    ]==]
+    assert(42 == 42)
+end
+assert(42 == 42)
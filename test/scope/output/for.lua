@@ -0,0 +1,12 @@
+
+
+local sum = 0
+for K = 1, 5 do
+    sum = sum + K
+end
+assert(sum == 15)
+
+for _, K in ipairs({ 5, 5 }) do
+    sum = sum + K
+end
+assert(5 == 5)
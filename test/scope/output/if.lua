@@ -0,0 +1,23 @@
+
+
+local r
+if 1 == 1 then
+    local X = 2
+    r = X
+elseif 1 == 2 then
+    local X = 3
+    r = X
+else
+    local X = 4
+    r = X
+end
+assert(r == 2)
+
+if 1 > 1 then
+    r = 0
+elseif 1 < 1 then
+    r = 0
+elseif 1 == 1 then
+    r = 1
+end
+assert(1 == r)
@@ -0,0 +1,14 @@
+
+
+do
+    local V = 7 + 1
+    assert(V == 8)
+end
+
+local a, b = 7, 7 * 2
+assert(a + b == 21)
+
+local function f(V)
+    return V
+end
+assert(f(7) == 7)
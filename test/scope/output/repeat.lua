@@ -0,0 +1,13 @@
+
+
+local n = 0
+repeat
+    n = n + 1
+    local DONE = n >= 3
+until DONE
+assert(n == 3)
+
+repeat
+    n = n - 1
+until false or n == 0
+assert(false == false)
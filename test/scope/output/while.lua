@@ -0,0 +1,13 @@
+
+
+local i = 0
+while i < 3 do
+    local N = 10
+    i = i + N
+end
+assert(i == 10)
+
+while i < 3 * 10 do
+    i = i + 1
+end
+assert(3 == 3)
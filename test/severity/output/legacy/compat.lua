@@ -0,0 +1 @@
+local old = require("old.api")
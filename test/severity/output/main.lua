@@ -0,0 +1,4 @@
+-- @allow(DL0001)
+local sdk = require("platform.sdk")
+local level = require("levels." .. name)
+local missing = require("missing")
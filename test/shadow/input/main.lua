@@ -18,6 +18,21 @@ end
 
 assert(bar(5) == 6)
 
+do
+    local a, v <const> = 2, 3
+    assert(a + v == 5)
+end
+
+for v = 1, 3 do
+    assert(v <= 3)
+end
+
+for k, v in pairs({ 1 }) do
+    assert(k == v)
+end
+
+assert(v == 1)
+
 function animation_db:load()
 	local function load_ani_file(f)
 		local ok, achunk = pcall(FS.load, f)
@@ -0,0 +1,12 @@
+-- @macro
+function SWAP(a, b)
+	local t = a
+	a = b
+	b = t
+end
+-- @macro
+LIMIT = 100
+
+local x, y = 1, LIMIT
+SWAP(x, y)
+print(x, y)
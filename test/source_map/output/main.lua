@@ -0,0 +1,7 @@
+
+
+local x, y = 1, 100
+local t = x
+	x = y
+	y = t
+print(x, y)
@@ -0,0 +1,4 @@
+-- @export
+require("macros")
+-- @macro
+SPEED = 2
@@ -0,0 +1,3 @@
+-- macros
+-- @macro
+ITER = 3
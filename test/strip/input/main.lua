@@ -0,0 +1,5 @@
+require("game")
+local ok = require("macros")
+local t = require("macros").x
+print(ITER, SPEED, ok)
+print(1)
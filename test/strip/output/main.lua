@@ -0,0 +1,5 @@
+
+local ok = true
+local t = (true).x
+print(3, 2, ok)
+print(1)
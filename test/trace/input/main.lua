@@ -0,0 +1,11 @@
+-- @macro
+V = 10
+-- @macro
+function SWAP(a, b)
+	local t = a
+	a = b
+	b = t
+end
+local x, y = V, V
+SWAP(x, y)
+print(x + V)
@@ -0,0 +1,11 @@
+
+
+
+
+
+
+
+
+local x, y = 10, 10
+local t = x x = y y = t
+print(x + 10)
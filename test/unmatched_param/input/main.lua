@@ -0,0 +1,5 @@
+-- @macro
+function ADD(a ], b)
+    return a + b
+end
+print(ADD(1, 2))
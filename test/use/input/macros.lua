@@ -0,0 +1,8 @@
+-- @macro
+ITER = 1000
+-- @macro
+function ADDSELF(x, value)
+	x = x + value
+end
+-- @macro
+SPEED = 3
@@ -0,0 +1,6 @@
+-- @use macros { ITER, ADDSELF as add, }
+local x = 0
+for i = 1, ITER do
+	add(x, i)
+end
+print(SPEED, ADDSELF)
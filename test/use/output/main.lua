@@ -0,0 +1,6 @@
+
+local x = 0
+for i = 1, 1000 do
+	x = x + i
+end
+print(SPEED, ADDSELF)
@@ -0,0 +1,9 @@
+-- @macro
+function SUM3()
+	local a = 1
+	local b = 2
+	return a + b
+end
+
+local x = 2
+x = x + SUM3()
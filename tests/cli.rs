@@ -0,0 +1,222 @@
+//! 子命令的测试，在 test/ 下的用例上运行
+
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, Output, Stdio},
+};
+
+const DLUA: &str = env!("CARGO_BIN_EXE_dlua");
+
+/// 在用例目录下运行 dlua
+fn dlua(fixture: &str, args: &[&str]) -> Output {
+    Command::new(DLUA)
+        .current_dir(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("test")
+                .join(fixture),
+        )
+        .args(args)
+        .output()
+        .expect("failed to run dlua")
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn resolve_lists_candidates() {
+    let output = dlua("entry_dot", &["resolve", "lib.util", "input"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "Resolving 'lib.util' in input\n\
+         \x20  1. ./?.lua                  input/lib/util.lua  found\n\
+         \x20  2. ./?/init.lua             input/lib/util/init.lua  not found\n\
+         => input/lib/util.lua\n"
+    );
+}
+
+#[test]
+fn resolve_reports_missing_module() {
+    let output = dlua("entry_dot", &["resolve", "missing", "input"]);
+    assert!(!output.status.success());
+    assert!(stdout(&output).is_empty());
+    let stderr = stderr(&output);
+    assert!(
+        stderr.contains("error[DL0001]: module 'missing' not found in input"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("input/missing/init.lua  not found"),
+        "{}",
+        stderr
+    );
+}
+
+/// 默认以当前目录 `.` 为源码根目录，--from 的模块要能在关系图中找到
+#[test]
+fn graph_from_module_in_current_directory() {
+    let output = dlua("entry_dot/input", &["graph", "--from", "main"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "digraph requires {\n\
+         \x20   rankdir=LR;\n\
+         \x20   node [shape=box];\n\
+         \x20   \"lib/util.lua\";\n\
+         \x20   \"macros.lua\";\n\
+         \x20   \"main.lua\";\n\
+         \x20   \"main.lua\" -> \"macros.lua\";\n\
+         \x20   \"main.lua\" -> \"lib/util.lua\";\n\
+         }\n"
+    );
+}
+
+#[test]
+fn graph_reports_missing_start_module() {
+    let output = dlua("entry_dot/input", &["graph", "--from", "missing"]);
+    assert!(!output.status.success());
+    assert!(stdout(&output).is_empty());
+    assert!(
+        stderr(&output).contains("error[DL0001]: module 'missing' not found in ."),
+        "{}",
+        stderr(&output)
+    );
+}
+
+/// 用 test/trace 的源码映射改写 traceback：带列号时按列找到所在的一段，
+/// 只有行号时整行来自同一个宏才注明宏名，否则列出行中的宏展开
+#[test]
+fn trace_attributes_macros_by_column() {
+    let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join("cli/trace/out");
+    let _ = std::fs::remove_dir_all(&out);
+    let out_str = out.to_str().unwrap();
+    let build = dlua(
+        "trace",
+        &["input", out_str, "-q", "--preserve-lines", "--source-map"],
+    );
+    assert!(build.status.success(), "{}", stderr(&build));
+
+    let mut child = Command::new(DLUA)
+        .args(["trace", out_str])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run dlua");
+    let traceback = "game/main.lua:10: error\n\
+                     main.lua:9: error\n\
+                     main.lua:9:18: error\n\
+                     main.lua:9:16: error\n\
+                     main.lua:11: error\n\
+                     other.lua:3: error\n";
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(traceback.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let source = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("test/trace/input/main.lua")
+        .canonicalize()
+        .unwrap();
+    assert_eq!(
+        stdout(&output).replace(source.to_str().unwrap(), "$SRC"),
+        "$SRC:10 (in expansion of macro SWAP): error\n\
+         $SRC:9 (line contains expansions of macros V at column 14, V at column 17): error\n\
+         $SRC:9:17 (in expansion of macro V): error\n\
+         $SRC:9:15: error\n\
+         $SRC:11 (line contains an expansion of macro V at column 11): error\n\
+         other.lua:3: error\n"
+    );
+}
+
+/// 选项可以写在位置参数之前，带值选项的值不会被当作位置参数
+#[test]
+fn flags_before_positionals() {
+    let output = dlua(
+        "entry_dot",
+        &["graph", "--format", "json", "--from", "main", "input"],
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stdout(&output).contains("\"main.lua\""),
+        "{}",
+        stdout(&output)
+    );
+
+    let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join("cli/flags/out");
+    let _ = std::fs::remove_dir_all(&out);
+    let output = dlua(
+        "entry_dot",
+        &[
+            "--full",
+            "-q",
+            "--color",
+            "never",
+            "input",
+            out.to_str().unwrap(),
+        ],
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(out.join("main.lua").is_file());
+}
+
+#[test]
+fn unknown_option_is_rejected() {
+    let output = dlua("entry_dot", &["--fulll", "input", "out"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("Unknown option '--fulll'"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn missing_positionals_print_usage() {
+    let output = dlua("entry_dot", &["input"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("Usage: dlua <entry> <export_path> [options]"),
+        "{}",
+        stderr(&output)
+    );
+
+    let output = dlua("entry_dot", &["trace"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("Usage: dlua trace <export_path>"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+/// require 与 `-- @export` 再导出的模块之间的关系
+#[test]
+fn graph_lists_requires() {
+    let output = dlua("export", &["graph", "input"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "digraph requires {\n\
+         \x20   rankdir=LR;\n\
+         \x20   node [shape=box];\n\
+         \x20   \"game.lua\";\n\
+         \x20   \"macros.lua\";\n\
+         \x20   \"main.lua\";\n\
+         \x20   \"game.lua\" -> \"macros.lua\";\n\
+         \x20   \"main.lua\" -> \"game.lua\";\n\
+         }\n"
+    );
+}
@@ -0,0 +1,228 @@
+//! 运行 test/ 下的所有用例。每个用例是一个目录：
+//!
+//! - `input/`：源码，在用例目录下以 `dlua input <导出目录>` 构建，因此会读取用例目录中的 `dlua.json`；
+//! - `args`：可选，追加的命令行参数，以空白分隔，其中的 `$OUT` 替换为导出目录；
+//! - `output/`：期望的导出目录（不含缓存）。没有这个目录时构建必须失败，并且不写入任何输出文件；
+//! - `stdout`、`stderr`：可选，期望的标准输出与标准错误。
+//!
+//! 比较之前，输出中用例目录的绝对路径替换为 `$FIXTURE`，导出目录替换为 `$OUT`。
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+const DLUA: &str = env!("CARGO_BIN_EXE_dlua");
+
+/// 用例的期望结果与实际结果之间的差异
+struct Mismatch {
+    what: String,
+    expected: String,
+    actual: String,
+}
+
+fn fixture_dirs() -> Vec<PathBuf> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("test");
+    let mut dirs: Vec<PathBuf> = fs::read_dir(&root)
+        .expect("failed to read test/")
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.join("input").is_dir())
+        .collect();
+    dirs.sort();
+    dirs
+}
+
+/// 用例自己的导出目录，每次运行前清空
+fn export_dir(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"))
+        .join("fixtures")
+        .join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir.join("out")
+}
+
+/// 在 cwd 下运行 `dlua <entry> <out> <args 文件中的参数>`
+fn run_dlua(fixture: &Path, cwd: &Path, entry: &str, out: &Path) -> Output {
+    let args = fs::read_to_string(fixture.join("args")).unwrap_or_default();
+    let out_str = out.to_str().unwrap();
+    Command::new(DLUA)
+        .current_dir(cwd)
+        .arg(entry)
+        .arg(out)
+        .args(
+            args.split_whitespace()
+                .map(|arg| arg.replace("$OUT", out_str)),
+        )
+        .env_remove("NO_COLOR")
+        .output()
+        .expect("failed to run dlua")
+}
+
+/// 把输出中与运行环境有关的路径替换为占位符
+fn normalize(text: &str, fixture: &Path, out: &Path) -> String {
+    let fixture = fixture.canonicalize().unwrap();
+    text.replace(out.to_str().unwrap(), "$OUT")
+        .replace(fixture.to_str().unwrap(), "$FIXTURE")
+}
+
+/// 目录下除缓存以外的所有文件，相对于 dir 的路径
+fn files_in(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(current) = stack.pop() {
+        let Ok(entries) = fs::read_dir(&current) else {
+            continue;
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            if path.is_dir() {
+                stack.push(path);
+            } else if !entry.file_name().to_string_lossy().starts_with(".dlua") {
+                files.push(path.strip_prefix(dir).unwrap().to_path_buf());
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// 比较一次运行的结果与用例的期望
+fn check(fixture: &Path, out: &Path, result: &Output) -> Vec<Mismatch> {
+    let mut mismatches = Vec::new();
+    let stderr = normalize(&String::from_utf8_lossy(&result.stderr), fixture, out);
+    let stdout = normalize(&String::from_utf8_lossy(&result.stdout), fixture, out);
+
+    let expected_output = fixture.join("output");
+    if expected_output.is_dir() {
+        if !result.status.success() {
+            mismatches.push(Mismatch {
+                what: "exit status".to_string(),
+                expected: "success".to_string(),
+                actual: format!("{}\n{}", result.status, stderr),
+            });
+        }
+        let expected_files = files_in(&expected_output);
+        let actual_files = files_in(out);
+        if expected_files != actual_files {
+            mismatches.push(Mismatch {
+                what: "exported files".to_string(),
+                expected: format!("{:?}", expected_files),
+                actual: format!("{:?}", actual_files),
+            });
+        }
+        for file in expected_files.iter().filter(|f| actual_files.contains(f)) {
+            let expected = fs::read_to_string(expected_output.join(file)).unwrap();
+            let actual = normalize(&fs::read_to_string(out.join(file)).unwrap(), fixture, out);
+            if expected != actual {
+                mismatches.push(Mismatch {
+                    what: format!("output/{}", file.display()),
+                    expected,
+                    actual,
+                });
+            }
+        }
+    } else {
+        if result.status.success() {
+            mismatches.push(Mismatch {
+                what: "exit status".to_string(),
+                expected: "failure".to_string(),
+                actual: result.status.to_string(),
+            });
+        }
+        let written = files_in(out);
+        if !written.is_empty() {
+            mismatches.push(Mismatch {
+                what: "exported files".to_string(),
+                expected: "[]".to_string(),
+                actual: format!("{:?}", written),
+            });
+        }
+    }
+
+    for (name, actual) in [("stdout", stdout), ("stderr", stderr)] {
+        if let Ok(expected) = fs::read_to_string(fixture.join(name))
+            && expected != actual
+        {
+            mismatches.push(Mismatch {
+                what: name.to_string(),
+                expected,
+                actual,
+            });
+        }
+    }
+    mismatches
+}
+
+fn report(failures: Vec<(String, Vec<Mismatch>)>) {
+    if failures.is_empty() {
+        return;
+    }
+    let mut message = String::new();
+    for (name, mismatches) in &failures {
+        for mismatch in mismatches {
+            message.push_str(&format!(
+                "--- {}: {}\n--- expected:\n{}\n--- actual:\n{}\n\n",
+                name, mismatch.what, mismatch.expected, mismatch.actual
+            ));
+        }
+    }
+    panic!("{} fixture(s) failed\n\n{}", failures.len(), message);
+}
+
+#[test]
+fn fixtures() {
+    let mut failures = Vec::new();
+    for fixture in fixture_dirs() {
+        let name = fixture.file_name().unwrap().to_string_lossy().to_string();
+        let out = export_dir(&name);
+        let result = run_dlua(&fixture, &fixture, "input", &out);
+        let mismatches = check(&fixture, &out, &result);
+        if !mismatches.is_empty() {
+            failures.push((name, mismatches));
+        }
+    }
+    report(failures);
+}
+
+/// 递归复制目录
+fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap().filter_map(Result::ok) {
+        let target = to.join(entry.file_name());
+        if entry.path().is_dir() {
+            copy_dir(&entry.path(), &target);
+        } else {
+            fs::copy(entry.path(), target).unwrap();
+        }
+    }
+}
+
+/// 以 `.` 作为源码根目录时，require 解析出的路径要与遍历目录得到的路径一致，
+/// 否则宏所在的文件改变后找不到 require 它的文件
+#[test]
+fn entry_dot() {
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("test/entry_dot");
+    let out = export_dir("entry_dot-dot");
+    let src = out.with_file_name("src");
+    copy_dir(&fixture.join("input"), &src);
+
+    let result = run_dlua(&fixture, &src, ".", &out);
+    let mismatches = check(&fixture, &out, &result);
+    if !mismatches.is_empty() {
+        report(vec![("entry_dot (entry .)".to_string(), mismatches)]);
+    }
+
+    let macros = src.join("macros.lua");
+    let text = fs::read_to_string(&macros).unwrap();
+    fs::write(&macros, text.replace("SPEED = 16", "SPEED = 32")).unwrap();
+    let result = run_dlua(&fixture, &src, ".", &out);
+    assert!(result.status.success());
+    let main = fs::read_to_string(out.join("main.lua")).unwrap();
+    assert!(
+        main.contains("local speed = 32"),
+        "main.lua was not rebuilt:\n{}",
+        main
+    );
+}
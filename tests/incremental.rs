@@ -0,0 +1,201 @@
+//! 增量构建的测试。复制 test/ 下的用例到临时目录构建一次，把输出文件都改写为 `stale`，
+//! 修改源码、配置或缓存之后再构建一次，没有被改回来的文件就是这次构建跳过的文件。
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+const DLUA: &str = env!("CARGO_BIN_EXE_dlua");
+const STALE: &str = "stale";
+
+/// 复制到临时目录的用例，源码在 `input/`，输出到 `out/`
+struct Workspace {
+    dir: PathBuf,
+}
+
+impl Workspace {
+    fn new(fixture: &str, name: &str) -> Self {
+        let dir = Path::new(env!("CARGO_TARGET_TMPDIR"))
+            .join("incremental")
+            .join(name);
+        let _ = fs::remove_dir_all(&dir);
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("test")
+            .join(fixture);
+        copy_dir(&fixture.join("input"), &dir.join("input"));
+        Workspace { dir }
+    }
+
+    fn run(&self) -> Output {
+        Command::new(DLUA)
+            .current_dir(&self.dir)
+            .args(["input", "out", "-q"])
+            .output()
+            .expect("failed to run dlua")
+    }
+
+    fn build(&self) {
+        let output = self.run();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    fn out(&self) -> PathBuf {
+        self.dir.join("out")
+    }
+
+    /// 把所有输出文件改写为 STALE
+    fn mark_stale(&self) {
+        for file in outputs(&self.out()) {
+            fs::write(self.out().join(file), STALE).unwrap();
+        }
+    }
+
+    /// 上次 mark_stale 之后重新写入的输出文件
+    fn rewritten(&self) -> Vec<String> {
+        outputs(&self.out())
+            .into_iter()
+            .filter(|file| fs::read_to_string(self.out().join(file)).unwrap() != STALE)
+            .collect()
+    }
+
+    /// 按 f 修改 input/ 下的源码文件
+    fn edit(&self, file: &str, f: impl FnOnce(String) -> String) {
+        let path = self.dir.join("input").join(file);
+        let text = fs::read_to_string(&path).unwrap();
+        fs::write(&path, f(text)).unwrap();
+    }
+
+    /// 按 f 修改增量构建的缓存
+    fn edit_cache(&self, f: impl FnOnce(&mut serde_json::Value)) {
+        let path = self.out().join(".dlua_cache.json");
+        let mut cache: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        f(&mut cache);
+        fs::write(&path, cache.to_string()).unwrap();
+    }
+}
+
+/// 递归复制目录
+fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap().filter_map(Result::ok) {
+        let target = to.join(entry.file_name());
+        if entry.path().is_dir() {
+            copy_dir(&entry.path(), &target);
+        } else {
+            fs::copy(entry.path(), target).unwrap();
+        }
+    }
+}
+
+/// 导出目录下除缓存以外的文件，按名字排序
+fn outputs(dir: &Path) -> Vec<String> {
+    let mut files: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .filter_map(Result::ok)
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| !name.starts_with(".dlua"))
+        .collect();
+    files.sort();
+    files
+}
+
+const ALL: [&str; 3] = ["game.lua", "macros.lua", "main.lua"];
+
+#[test]
+fn unchanged_files_are_skipped() {
+    let ws = Workspace::new("export", "unchanged");
+    ws.build();
+    ws.mark_stale();
+    // 内容不变、只有修改时间变了的文件同样跳过
+    ws.edit("main.lua", |text| text);
+    ws.build();
+    assert_eq!(ws.rewritten(), Vec::<String>::new());
+}
+
+#[test]
+fn cache_version_change_rebuilds_everything() {
+    let ws = Workspace::new("export", "cache_version");
+    ws.build();
+    ws.mark_stale();
+    ws.edit_cache(|cache| cache["version"] = 0.into());
+    ws.build();
+    assert_eq!(ws.rewritten(), ALL);
+
+    ws.mark_stale();
+    ws.edit_cache(|cache| cache["dlua_version"] = "0.0.0".into());
+    ws.build();
+    assert_eq!(ws.rewritten(), ALL);
+}
+
+#[test]
+fn config_change_rebuilds_everything() {
+    let ws = Workspace::new("export", "config");
+    ws.build();
+    ws.mark_stale();
+    fs::write(ws.dir.join("dlua.json"), r#"{ "dialect": "lua51" }"#).unwrap();
+    ws.build();
+    assert_eq!(ws.rewritten(), ALL);
+}
+
+#[test]
+fn severity_change_reports_unchanged_files() {
+    let ws = Workspace::new("export", "severity");
+    ws.edit("main.lua", |text| format!("require(\"missing\")\n{}", text));
+    ws.build();
+    // main.lua 没有改变，但 DL0001 升级为错误之后必须重新检查
+    fs::write(
+        ws.dir.join("dlua.json"),
+        r#"{ "diagnostics": { "DL0001": "error" } }"#,
+    )
+    .unwrap();
+    let output = ws.run();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("DL0001"), "{}", stderr);
+}
+
+#[test]
+fn plain_code_change_rewrites_only_that_file() {
+    let ws = Workspace::new("export", "plain_code");
+    ws.build();
+    ws.mark_stale();
+    // game.lua 导出的宏不变，require 它的 main.lua 不需要重新展开
+    ws.edit("game.lua", |text| {
+        text.replace("local game = {}", "local game = { name = \"game\" }")
+    });
+    ws.build();
+    assert_eq!(ws.rewritten(), ["game.lua"]);
+}
+
+#[test]
+fn macro_change_propagates_through_export() {
+    let ws = Workspace::new("export", "export");
+    ws.build();
+    ws.mark_stale();
+    // main.lua 通过 game.lua 的 `-- @export require("macros")` 使用 SPEED
+    ws.edit("macros.lua", |text| {
+        text.replace("SPEED = 16", "SPEED = 32")
+    });
+    ws.build();
+    assert_eq!(ws.rewritten(), ["game.lua", "macros.lua", "main.lua"]);
+    let main = fs::read_to_string(ws.out().join("main.lua")).unwrap();
+    assert!(main.contains("local x = 32 * 9.8"), "{}", main);
+}
+
+#[test]
+fn unchanged_macros_do_not_propagate() {
+    let ws = Workspace::new("export", "same_macros");
+    ws.build();
+    ws.mark_stale();
+    // 只改了注释，导出的宏与之前相同
+    ws.edit("macros.lua", |text| format!("-- 常用的宏\n{}", text));
+    ws.build();
+    assert_eq!(ws.rewritten(), ["macros.lua"]);
+}